@@ -29,6 +29,12 @@ impl App {
     }
 }
 
+#[cfg(not(target_arch = "wasm32"))]
+impl Default for App {
+    fn default() -> Self {
+        Self::new()
+    }
+}
 
 impl ApplicationHandler<State> for App {
     fn resumed(&mut self, event_loop: &ActiveEventLoop) {
@@ -120,8 +126,9 @@ impl ApplicationHandler<State> for App {
                     Ok(_) => {}
                     // Reconfigure the surface if it's lost or outdated
                     Err(wgpu::SurfaceError::Lost | wgpu::SurfaceError::Outdated) => {
-                        let size = state.window.inner_size();
-                        state.resize(size.width, size.height);
+                        if let Some(size) = state.window().map(|window| window.inner_size()) {
+                            state.resize(size.width, size.height);
+                        }
                     }
                     Err(e) => {
                         log::error!("Unable to render {}", e);
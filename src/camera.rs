@@ -2,7 +2,7 @@ use cgmath::Vector3;
 use wgpu::util::DeviceExt;
 use winit::keyboard::KeyCode;
 
-use crate::OPENGL_TO_WGPU_MATRIX;
+use crate::{utils::oblique_near_plane, OPENGL_TO_WGPU_MATRIX};
 pub struct Camera {
     pub eye: cgmath::Point3<f32>,
     pub target: cgmath::Point3<f32>,
@@ -38,7 +38,7 @@ impl Camera {
         // 1.
         let view = cgmath::Matrix4::look_at_rh(self.eye, self.target, self.up);
         // 2.
-        let proj = cgmath::perspective(cgmath::Deg(self.fovy), self.aspect, self.znear, self.zfar);
+        let proj = self.build_gl_projection_matrix();
 
         // 3.
         OPENGL_TO_WGPU_MATRIX * proj * view
@@ -49,18 +49,39 @@ impl Camera {
     }
 
     pub fn build_proj_only_matrix(&self) -> cgmath::Matrix4<f32> {
-        let proj = cgmath::perspective(cgmath::Deg(self.fovy), self.aspect, self.znear, self.zfar);
-        OPENGL_TO_WGPU_MATRIX * proj
+        OPENGL_TO_WGPU_MATRIX * self.build_gl_projection_matrix()
     }
 
-    pub fn build_reflected_camera(&self, reflection: cgmath::Matrix4<f32>) -> cgmath::Matrix4<f32> {
-        // 3. Build reflected camera
-        // /  TODO CLEAN HACK
+    fn build_gl_projection_matrix(&self) -> cgmath::Matrix4<f32> {
+        cgmath::perspective(cgmath::Deg(self.fovy), self.aspect, self.znear, self.zfar)
+    }
+
+    // `clip_plane` is the mirror plane in world space, positive on the reflective side.
+    // When given, the near plane of the reflected camera is moved onto it so nothing
+    // behind the mirror leaks into the reflection. Also returns whether it was: it can't
+    // be with the eye behind the plane, and callers then fall back to the discard test.
+    pub fn build_reflected_camera(
+        &self,
+        reflection: cgmath::Matrix4<f32>,
+        clip_plane: Option<cgmath::Vector4<f32>>,
+    ) -> (cgmath::Matrix4<f32>, bool) {
+        use cgmath::{Matrix, SquareMatrix};
+
         let view = self.build_view_only_matrix();
-        let projection = self.build_proj_only_matrix();
         let reflected_view = view * reflection;
-        projection * reflected_view
-        // /
+        let projection = self.build_gl_projection_matrix();
+
+        // Planes transform with the inverse transpose
+        let oblique = clip_plane.and_then(|plane| {
+            let view_plane = reflected_view.invert()?.transpose() * plane;
+            oblique_near_plane(projection, view_plane)
+        });
+        let clipped = oblique.is_some();
+
+        (
+            OPENGL_TO_WGPU_MATRIX * oblique.unwrap_or(projection) * reflected_view,
+            clipped,
+        )
     }
 }
 
@@ -72,6 +93,8 @@ pub struct CameraUniform {
     // We can't use cgmath with bytemuck directly, so we'll have
     // to convert the Matrix4 into a 4x4 f32 array
     view_proj: [[f32; 4]; 4],
+    // Eye position, for view-dependent shading (environment reflections)
+    view_position: [f32; 4],
 }
 
 impl Default for CameraUniform {
@@ -84,11 +107,20 @@ impl CameraUniform {
         use cgmath::SquareMatrix;
         Self {
             view_proj: cgmath::Matrix4::identity().into(),
+            view_position: [0.0; 4],
         }
     }
 
     pub fn update_view_proj(&mut self, camera: &Camera) {
         self.view_proj = camera.build_view_projection_matrix().into();
+        self.view_position = camera.eye.to_homogeneous().into();
+    }
+
+    pub fn from_view_proj(view_proj: cgmath::Matrix4<f32>, eye: cgmath::Point3<f32>) -> Self {
+        Self {
+            view_proj: view_proj.into(),
+            view_position: eye.to_homogeneous().into(),
+        }
     }
 }
 
@@ -1,76 +1,16 @@
-use anyhow::*;
+use crate::pipeline_desc::{shader, PipelineDesc};
 
-pub struct Pipeline {
-    pub pipeline: wgpu::RenderPipeline,
-}
+pub struct Pipeline;
 
 impl Pipeline {
     pub fn debug_render_pipeline(
-        device: &wgpu::Device,
         config: &wgpu::SurfaceConfiguration,
         sample_count: u32,
-    ) -> Result<Pipeline> {
-        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
-            label: Some("Debug"),
-            source: wgpu::ShaderSource::Wgsl(include_str!("shaders/debug.wgsl").into()),
-        });
-
-        let debug_pipeline_layout =
-            device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
-                label: Some("Render Pipeline Layout"),
-                bind_group_layouts: &[],
-                push_constant_ranges: &[],
-            });
-
-        let debug_stencil_pipeline =
-            device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
-                label: Some("Stencil Debug Pipeline"),
-                layout: Some(&debug_pipeline_layout),
-                vertex: wgpu::VertexState {
-                    module: &shader,
-                    entry_point: Some("vs_main"),
-                    buffers: &[], // fullscreen triangle
-                    compilation_options: Default::default(),
-                },
-                fragment: Some(wgpu::FragmentState {
-                    module: &shader,
-                    entry_point: Some("fs_main"),
-                    targets: &[Some(wgpu::ColorTargetState {
-                        format: config.format,
-                        blend: None,
-                        write_mask: wgpu::ColorWrites::ALL,
-                    })],
-                    compilation_options: Default::default(),
-                }),
-                primitive: wgpu::PrimitiveState::default(),
-                depth_stencil: Some(wgpu::DepthStencilState {
-                    format: wgpu::TextureFormat::Depth24PlusStencil8,
-                    depth_write_enabled: false,
-                    depth_compare: wgpu::CompareFunction::Always,
-                    stencil: wgpu::StencilState {
-                        front: wgpu::StencilFaceState {
-                            compare: wgpu::CompareFunction::Equal,
-                            fail_op: wgpu::StencilOperation::Keep,
-                            depth_fail_op: wgpu::StencilOperation::Keep,
-                            pass_op: wgpu::StencilOperation::Keep,
-                        },
-                        back: wgpu::StencilFaceState::IGNORE,
-                        read_mask: 0xFF,
-                        write_mask: 0x00,
-                    },
-                    bias: Default::default(),
-                }),
-                multisample: wgpu::MultisampleState {
-                    count: sample_count,              // 2.
-                    mask: !0,                         // 3.
-                    alpha_to_coverage_enabled: false, // 4.
-                },
-                multiview: None,
-                cache: None,
-            });
-
-        Ok(Self {
-            pipeline: debug_stencil_pipeline,
-        })
+    ) -> PipelineDesc {
+        // fullscreen triangle, no vertex buffers
+        PipelineDesc::new("Stencil Debug Pipeline", shader!("Debug", "debug.wgsl"))
+            .target(config.format, None)
+            .stencil(wgpu::CompareFunction::Equal, wgpu::StencilOperation::Keep)
+            .sample_count(sample_count)
     }
 }
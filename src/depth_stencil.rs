@@ -2,14 +2,18 @@ pub struct StencilTexture {
     #[allow(unused)]
     pub texture: wgpu::Texture,
     pub view: wgpu::TextureView,
+    // Depth aspect only, for sampling in later passes (screen-space reflections)
+    pub depth_view: wgpu::TextureView,
 }
 
 impl StencilTexture {
+    // `sampled`: also bindable as a texture, which only screen-space reflections need.
     pub fn create_stencil_texture(
         device: &wgpu::Device,
         config: &wgpu::SurfaceConfiguration,
         label: &str,
         sample_count: u32,
+        sampled: bool,
     ) -> Self {
         let size = wgpu::Extent3d {
             width: config.width.max(1),
@@ -18,23 +22,33 @@ impl StencilTexture {
         };
 
         let depth_stencil_format = wgpu::TextureFormat::Depth24PlusStencil8;
+        let mut usage = wgpu::TextureUsages::RENDER_ATTACHMENT;
+        if sampled {
+            usage |= wgpu::TextureUsages::TEXTURE_BINDING;
+        }
 
         let depth_stencil_texture = device.create_texture(&wgpu::TextureDescriptor {
             label: Some(label),
             size,
             mip_level_count: 1,
-            sample_count: sample_count,
+            sample_count,
             dimension: wgpu::TextureDimension::D2,
             format: depth_stencil_format,
-            usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
+            usage,
             view_formats: &[],
         });
 
         let depth_stencil_view = depth_stencil_texture.create_view(&Default::default());
+        let depth_view = depth_stencil_texture.create_view(&wgpu::TextureViewDescriptor {
+            label: Some("depth_view"),
+            aspect: wgpu::TextureAspect::DepthOnly,
+            ..Default::default()
+        });
 
         Self {
             texture: depth_stencil_texture,
             view: depth_stencil_view,
+            depth_view,
         }
     }
 }
@@ -0,0 +1,226 @@
+use std::rc::Rc;
+
+use cgmath::{EuclideanSpace, Matrix4, Point3, Vector3};
+
+use crate::{
+    camera::{bind_group_for_camera_uniform, create_camera_buffer, CameraUniform},
+    depth_stencil::StencilTexture,
+    model::{DrawModel, Model},
+    vertex::{create_instance_buffer, Instance},
+    OPENGL_TO_WGPU_MATRIX,
+};
+
+// Edge of one cube map face, in texels.
+pub const ENVIRONMENT_MAP_SIZE: u32 = 256;
+
+// Forward and up of each cube face, in wgpu's face order (+X, -X, +Y, -Y, +Z, -Z).
+const CUBE_FACES: [(Vector3<f32>, Vector3<f32>); 6] = [
+    (Vector3::new(1.0, 0.0, 0.0), Vector3::new(0.0, 1.0, 0.0)),
+    (Vector3::new(-1.0, 0.0, 0.0), Vector3::new(0.0, 1.0, 0.0)),
+    (Vector3::new(0.0, 1.0, 0.0), Vector3::new(0.0, 0.0, -1.0)),
+    (Vector3::new(0.0, -1.0, 0.0), Vector3::new(0.0, 0.0, 1.0)),
+    (Vector3::new(0.0, 0.0, 1.0), Vector3::new(0.0, 1.0, 0.0)),
+    (Vector3::new(0.0, 0.0, -1.0), Vector3::new(0.0, 1.0, 0.0)),
+];
+
+// Dynamic cube map rendered from a point of the scene.
+pub struct EnvironmentMap {
+    #[allow(unused)]
+    pub texture: wgpu::Texture,
+    pub face_views: Vec<wgpu::TextureView>,
+    pub depth_stencil: StencilTexture,
+    face_cameras: Vec<(wgpu::Buffer, wgpu::BindGroup)>,
+    pub bind_group: wgpu::BindGroup,
+    // Render the faces every `update_interval` frames, 0 = only once.
+    pub update_interval: u32,
+    frames_since_update: Option<u32>,
+}
+
+impl EnvironmentMap {
+    pub fn new(device: &wgpu::Device, config: &wgpu::SurfaceConfiguration) -> Self {
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("environment_map"),
+            size: wgpu::Extent3d {
+                width: ENVIRONMENT_MAP_SIZE,
+                height: ENVIRONMENT_MAP_SIZE,
+                depth_or_array_layers: 6,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: config.format,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING,
+            view_formats: &[],
+        });
+
+        let face_views = (0..6)
+            .map(|face| {
+                texture.create_view(&wgpu::TextureViewDescriptor {
+                    label: Some("environment_map_face"),
+                    dimension: Some(wgpu::TextureViewDimension::D2),
+                    base_array_layer: face,
+                    array_layer_count: Some(1),
+                    ..Default::default()
+                })
+            })
+            .collect();
+
+        // One depth buffer, reused face after face
+        let face_config = wgpu::SurfaceConfiguration {
+            width: ENVIRONMENT_MAP_SIZE,
+            height: ENVIRONMENT_MAP_SIZE,
+            ..config.clone()
+        };
+        let depth_stencil = StencilTexture::create_stencil_texture(
+            device,
+            &face_config,
+            "environment_depth",
+            1,
+            false,
+        );
+
+        let face_cameras = (0..6)
+            .map(|_| {
+                let buffer = create_camera_buffer(&CameraUniform::new(), device);
+                let (_, bind_group) = bind_group_for_camera_uniform(&buffer, device);
+                (buffer, bind_group)
+            })
+            .collect();
+
+        let cube_view = texture.create_view(&wgpu::TextureViewDescriptor {
+            label: Some("environment_map_cube"),
+            dimension: Some(wgpu::TextureViewDimension::Cube),
+            ..Default::default()
+        });
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            address_mode_w: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            mipmap_filter: wgpu::FilterMode::Nearest,
+            ..Default::default()
+        });
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("environment_map_bind_group"),
+            layout: &Self::bind_group_layout(device),
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(&cube_view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::Sampler(&sampler),
+                },
+            ],
+        });
+
+        Self {
+            texture,
+            face_views,
+            depth_stencil,
+            face_cameras,
+            bind_group,
+            update_interval: 1,
+            frames_since_update: None,
+        }
+    }
+
+    pub fn bind_group_layout(device: &wgpu::Device) -> wgpu::BindGroupLayout {
+        device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("environment_map_bind_group_layout"),
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        multisampled: false,
+                        view_dimension: wgpu::TextureViewDimension::Cube,
+                        sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                    count: None,
+                },
+            ],
+        })
+    }
+
+    // True once every `update_interval` frames; the first frame always renders.
+    pub fn due(&mut self) -> bool {
+        let due = match self.frames_since_update {
+            None => true,
+            Some(_) if self.update_interval == 0 => false,
+            Some(frames) => frames + 1 >= self.update_interval,
+        };
+        self.frames_since_update = match (due, self.frames_since_update) {
+            (true, _) => Some(0),
+            (false, frames) => frames.map(|f| f + 1),
+        };
+        due
+    }
+
+    // 90° cameras looking down each face from `position`.
+    pub fn update(&self, queue: &wgpu::Queue, position: Point3<f32>) {
+        for ((forward, up), (buffer, _)) in CUBE_FACES.iter().zip(&self.face_cameras) {
+            // Cube faces are laid out left-handed: build a left-handed view, then flip z
+            // back so the usual right-handed projection applies.
+            let view = Matrix4::from_nonuniform_scale(1.0, 1.0, -1.0)
+                * Matrix4::look_to_lh(position, *forward, *up);
+            let projection = cgmath::perspective(cgmath::Deg(90.0), 1.0, 0.1, 100.0);
+            let camera =
+                CameraUniform::from_view_proj(OPENGL_TO_WGPU_MATRIX * projection * view, position);
+            queue.write_buffer(buffer, 0, bytemuck::cast_slice(&[camera]));
+        }
+    }
+
+    pub fn face_camera(&self, face: usize) -> &wgpu::BindGroup {
+        &self.face_cameras[face].1
+    }
+}
+
+// Any loaded model drawn with a chrome-like material reflecting its surroundings.
+pub struct ReflectiveModel {
+    pub model: Rc<Model>,
+    pub instance: Instance,
+    pub instance_buffer: wgpu::Buffer,
+    pub environment: EnvironmentMap,
+}
+
+impl ReflectiveModel {
+    pub fn new(
+        device: &wgpu::Device,
+        config: &wgpu::SurfaceConfiguration,
+        model: Rc<Model>,
+        instance: Instance,
+        scale: f32,
+    ) -> Self {
+        let instance_buffer =
+            create_instance_buffer(device, &[instance.to_raw_with_uniform_scale(scale)]);
+        let environment = EnvironmentMap::new(device, config);
+
+        Self {
+            model,
+            instance,
+            instance_buffer,
+            environment,
+        }
+    }
+
+    // The cube map is captured from the model's origin.
+    pub fn position(&self) -> Point3<f32> {
+        Point3::from_vec(self.instance.translation().w.truncate())
+    }
+
+    pub fn draw<'a>(&'a self, pass: &mut wgpu::RenderPass<'a>) {
+        pass.set_vertex_buffer(1, self.instance_buffer.slice(..));
+        for mesh in &self.model.meshes {
+            pass.draw_mesh(mesh);
+        }
+    }
+}
@@ -104,8 +104,8 @@ impl Spin {
 pub struct MirrorPlaneUniform {
     normal: [f32; 3], // normal: world-space mirror normal
     _pad1: f32,
-    point: [f32; 3], // point: world-space point on the mirror plane
-    _pad2: f32,
+    point: [f32; 3],   // point: world-space point on the mirror plane
+    clip_discard: u32, // clip_discard: 1 = clip reflected fragments behind the plane in the shader
 }
 
 impl MirrorPlaneUniform {
@@ -117,33 +117,38 @@ impl MirrorPlaneUniform {
             normal: utils::normal_from_transform(mirror_transform, local_normal).into(),
             _pad1: 0.0,
             point: utils::point_from_transform(mirror_transform).into(),
-            _pad2: 0.0,
+            clip_discard: 0,
         }
     }
 
+    pub fn set_clip_discard(&mut self, discard: bool) {
+        self.clip_discard = discard as u32;
+    }
+
+    pub fn bind_group_layout(device: &wgpu::Device) -> wgpu::BindGroupLayout {
+        device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("mirror_plane_bind_group_layout"),
+            entries: &[wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStages::VERTEX_FRAGMENT,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: Some(
+                        std::num::NonZeroU64::new(std::mem::size_of::<MirrorPlaneUniform>() as u64)
+                            .unwrap(),
+                    ),
+                },
+                count: None,
+            }],
+        })
+    }
+
     pub fn create_bind_group_layout(
         device: &wgpu::Device,
         mirror_plane_buffer: &wgpu::Buffer,
     ) -> (wgpu::BindGroupLayout, wgpu::BindGroup) {
-        let mirror_plane_bind_group_layout =
-            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
-                label: Some("mirror_plane_bind_group_layout"),
-                entries: &[wgpu::BindGroupLayoutEntry {
-                    binding: 0,
-                    visibility: wgpu::ShaderStages::VERTEX,
-                    ty: wgpu::BindingType::Buffer {
-                        ty: wgpu::BufferBindingType::Uniform,
-                        has_dynamic_offset: false,
-                        min_binding_size: Some(
-                            std::num::NonZeroU64::new(
-                                std::mem::size_of::<MirrorPlaneUniform>() as u64
-                            )
-                            .unwrap(),
-                        ),
-                    },
-                    count: None,
-                }],
-            });
+        let mirror_plane_bind_group_layout = Self::bind_group_layout(device);
 
         let mirror_plane_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
             label: Some("mirror_plane_bind_group"),
@@ -165,3 +170,190 @@ impl MirrorPlaneUniform {
         })
     }
 }
+
+// Surface Uniform
+
+#[repr(C)]
+#[derive(Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct SurfaceUniform {
+    reflectivity: f32, // reflectivity: 0 = matte, 1 = screen-space reflection only
+    _pad: [f32; 3],
+}
+
+impl SurfaceUniform {
+    pub fn new(reflectivity: f32) -> SurfaceUniform {
+        Self {
+            reflectivity,
+            _pad: [0.0; 3],
+        }
+    }
+
+    pub fn bind_group_layout(device: &wgpu::Device) -> wgpu::BindGroupLayout {
+        device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("surface_bind_group_layout"),
+            entries: &[wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            }],
+        })
+    }
+
+    // Constant for the lifetime of the surface, the buffer is owned by the bind group.
+    pub fn create_bind_group(&self, device: &wgpu::Device) -> wgpu::BindGroup {
+        let buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Surface Buffer"),
+            contents: bytemuck::cast_slice(&[*self]),
+            usage: wgpu::BufferUsages::UNIFORM,
+        });
+
+        device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("surface_bind_group"),
+            layout: &Self::bind_group_layout(device),
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: buffer.as_entire_binding(),
+            }],
+        })
+    }
+}
+
+// Mirror Material Uniform
+
+#[repr(C)]
+#[derive(Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct MirrorMaterialUniform {
+    tint: [f32; 3],        // tint: color of the reflective coating
+    reflectance: f32,      // reflectance: Fresnel reflectance facing the mirror (F0)
+    roughness: f32,        // roughness: 0 = perfect mirror
+    dirt_strength: f32,    // dirt_strength: scale of the dirt map (red channel)
+    scratch_strength: f32, // scratch_strength: scale of the scratch map (red channel)
+    _pad: f32,
+}
+
+impl MirrorMaterialUniform {
+    pub fn new(
+        tint: [f32; 3],
+        reflectance: f32,
+        roughness: f32,
+        dirt_strength: f32,
+        scratch_strength: f32,
+    ) -> MirrorMaterialUniform {
+        Self {
+            tint,
+            reflectance,
+            roughness,
+            dirt_strength,
+            scratch_strength,
+            _pad: 0.0,
+        }
+    }
+
+    pub fn bind_group_layout(device: &wgpu::Device) -> wgpu::BindGroupLayout {
+        let map = |binding| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::FRAGMENT,
+            ty: wgpu::BindingType::Texture {
+                multisampled: false,
+                view_dimension: wgpu::TextureViewDimension::D2,
+                sample_type: wgpu::TextureSampleType::Float { filterable: true },
+            },
+            count: None,
+        };
+
+        device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("mirror_material_bind_group_layout"),
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                map(1), // dirt
+                map(2), // scratches
+                wgpu::BindGroupLayoutEntry {
+                    binding: 3,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                    count: None,
+                },
+            ],
+        })
+    }
+
+    pub fn create_bind_group(
+        &self,
+        device: &wgpu::Device,
+        dirt: &crate::texture::Texture,
+        scratches: &crate::texture::Texture,
+    ) -> wgpu::BindGroup {
+        let buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Mirror Material Buffer"),
+            contents: bytemuck::cast_slice(&[*self]),
+            usage: wgpu::BufferUsages::UNIFORM,
+        });
+
+        device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("mirror_material_bind_group"),
+            layout: &Self::bind_group_layout(device),
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::TextureView(&dirt.view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: wgpu::BindingResource::TextureView(&scratches.view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: wgpu::BindingResource::Sampler(&dirt.sampler),
+                },
+            ],
+        })
+    }
+}
+
+// Water Uniform
+
+#[repr(C)]
+#[derive(Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct WaterUniform {
+    tint: [f32; 3],   // tint: color picked up by the refracted light
+    reflectance: f32, // reflectance: Fresnel reflectance looking straight down (F0)
+    time: f32,        // time: seconds, scrolls the normal map
+    wave_speed: f32,  // wave_speed: normal map scroll, in texture repeats per second
+    distortion: f32,  // distortion: screen-space offset of reflection/refraction by the waves
+    _pad: f32,
+}
+
+impl WaterUniform {
+    pub fn new(tint: [f32; 3], reflectance: f32, wave_speed: f32, distortion: f32) -> WaterUniform {
+        Self {
+            tint,
+            reflectance,
+            time: 0.0,
+            wave_speed,
+            distortion,
+            _pad: 0.0,
+        }
+    }
+
+    pub fn advance(&mut self, dt: f32) {
+        self.time += dt;
+    }
+}
@@ -3,15 +3,34 @@ pub use app::App;
 pub mod camera;
 pub mod debugger;
 pub mod depth_stencil;
+pub mod environment;
 pub mod extra;
+pub mod mirror;
 pub mod model;
+pub mod msaa;
+pub mod offscreen;
 pub mod pipeline;
+pub mod pipeline_desc;
+pub mod portal;
+pub mod profiler;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod recording;
+pub mod render_graph;
 pub mod resources;
+pub mod screenshot;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod shader_watch;
+pub mod ssr;
+pub mod stats_overlay;
 pub mod state;
+pub mod surface;
 pub mod texture;
 pub mod utils;
 pub mod vertex;
+pub mod visibility;
+pub mod water;
 pub mod web_utils;
+pub mod wgsl;
 
 #[rustfmt::skip]
 pub const OPENGL_TO_WGPU_MATRIX: cgmath::Matrix4<f32> = cgmath::Matrix4::from_cols(
@@ -0,0 +1,782 @@
+use std::rc::Rc;
+
+use anyhow::Context;
+use bytemuck::Zeroable;
+use cgmath::{EuclideanSpace, InnerSpace, MetricSpace, Point3, SquareMatrix, Vector3};
+
+use crate::{
+    camera::{
+        bind_group_for_camera_uniform, create_camera_reflected_buffer, Camera, CameraUniform,
+    },
+    depth_stencil::StencilTexture,
+    extra::{MirrorMaterialUniform, MirrorPlaneUniform},
+    model::{DrawModel, Mesh},
+    portal::portal_view_transform,
+    resources,
+    texture::Texture,
+    utils::{build_reflection_matrix, normal_from_transform, point_from_transform},
+    vertex::{create_instance_buffer, Instance},
+    visibility::Frustum,
+};
+
+// Deepest mirror-in-mirror recursion. Every root mirror owns this many consecutive
+// stencil values: its own reference for the first bounce, then +1 per nested level.
+pub const MAX_REFLECTION_DEPTH: u32 = 8;
+
+// Stencil values are 8 bits wide and 0 means "no mirror".
+pub const MAX_MIRRORS: usize = u8::MAX as usize / MAX_REFLECTION_DEPTH as usize;
+
+// Nested mirrors drawn per frame, over all root mirrors. Each one redraws the whole scene,
+// and facing mirrors otherwise multiply at every bounce; past it deeper mirrors are dropped.
+pub const MAX_REFLECTION_NODES: usize = 64;
+
+// How far a vertex of a mirror mesh may sit off its plane, relative to the mesh radius.
+pub const MIRROR_PLANARITY_TOLERANCE: f32 = 1e-3;
+
+// How the reflected scene is cut at the mirror plane.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MirrorClipping {
+    // Near plane of the reflected camera moved onto the mirror plane.
+    Oblique,
+    // Per-fragment test against `MirrorPlaneUniform` in shader.wgsl (MIRROR_CLIP).
+    Discard,
+}
+
+impl MirrorClipping {
+    // The oblique frustum squeezes the whole depth range against the mirror plane,
+    // which the downlevel (WebGL2/GLES) adapters don't have the precision for.
+    pub fn for_adapter(adapter: &wgpu::Adapter) -> Self {
+        if adapter.get_downlevel_capabilities().is_webgpu_compliant() {
+            MirrorClipping::Oblique
+        } else {
+            MirrorClipping::Discard
+        }
+    }
+}
+
+// How a mirror shows its reflection.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MirrorTechnique {
+    // Reflected scene drawn straight into the framebuffer, masked by the stencil.
+    // Supports mirror-in-mirror recursion.
+    Stencil,
+    // Reflected scene drawn into an offscreen texture per mirror, sampled by the
+    // mirror surface in screen space. One bounce only, but the surface shader is free
+    // to distort, blur or tint the reflection.
+    RenderTexture,
+}
+
+// Resolution reflections are rendered at, relative to the framebuffer. Below full, the
+// stencil reflections go to an offscreen target and are stretched back over the mirrors.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ReflectionQuality {
+    #[default]
+    Full,
+    Half,
+    Quarter,
+}
+
+impl ReflectionQuality {
+    pub fn divisor(self) -> u32 {
+        match self {
+            Self::Full => 1,
+            Self::Half => 2,
+            Self::Quarter => 4,
+        }
+    }
+
+    pub fn next(self) -> Self {
+        match self {
+            Self::Full => Self::Half,
+            Self::Half => Self::Quarter,
+            Self::Quarter => Self::Full,
+        }
+    }
+
+    // The surface configuration, resized to this quality (for the reflection targets).
+    pub fn scaled_config(self, config: &wgpu::SurfaceConfiguration) -> wgpu::SurfaceConfiguration {
+        wgpu::SurfaceConfiguration {
+            width: (config.width / self.divisor()).max(1),
+            height: (config.height / self.divisor()).max(1),
+            ..config.clone()
+        }
+    }
+}
+
+// The two draws of a stencil mirror surface (mirror_surface.wgsl).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SurfaceLayer {
+    // Scales the reflection in the framebuffer by Fresnel, tint, dirt and scratches.
+    Reflection,
+    // Adds the light scattered by the coating where it does not reflect.
+    Coat,
+}
+
+impl SurfaceLayer {
+    pub fn entry_point(self) -> &'static str {
+        match self {
+            SurfaceLayer::Reflection => "fs_reflect",
+            SurfaceLayer::Coat => "fs_coat",
+        }
+    }
+
+    pub fn blend(self) -> wgpu::BlendState {
+        match self {
+            // framebuffer * shader output
+            SurfaceLayer::Reflection => wgpu::BlendState {
+                color: wgpu::BlendComponent {
+                    src_factor: wgpu::BlendFactor::Zero,
+                    dst_factor: wgpu::BlendFactor::Src,
+                    operation: wgpu::BlendOperation::Add,
+                },
+                alpha: wgpu::BlendComponent {
+                    src_factor: wgpu::BlendFactor::Zero,
+                    dst_factor: wgpu::BlendFactor::One,
+                    operation: wgpu::BlendOperation::Add,
+                },
+            },
+            // framebuffer + shader output
+            SurfaceLayer::Coat => wgpu::BlendState {
+                color: wgpu::BlendComponent {
+                    src_factor: wgpu::BlendFactor::One,
+                    dst_factor: wgpu::BlendFactor::One,
+                    operation: wgpu::BlendOperation::Add,
+                },
+                alpha: wgpu::BlendComponent {
+                    src_factor: wgpu::BlendFactor::Zero,
+                    dst_factor: wgpu::BlendFactor::One,
+                    operation: wgpu::BlendOperation::Add,
+                },
+            },
+        }
+    }
+}
+
+// Surface of a mirror: a tinted coating with Fresnel reflectance, blurred by roughness
+// and broken up by optional dirt and scratch maps (red channel, 1 = fully covered).
+#[derive(Clone)]
+pub struct MirrorMaterial {
+    pub tint: [f32; 3],
+    // Reflectance facing the mirror (F0), rising to 1 at grazing angles.
+    pub reflectance: f32,
+    // 0 = sharp. Blurs the reflection with `MirrorTechnique::RenderTexture`,
+    // hazes it with `MirrorTechnique::Stencil`.
+    pub roughness: f32,
+    pub dirt: Option<(Rc<Texture>, f32)>,
+    pub scratches: Option<(Rc<Texture>, f32)>,
+}
+
+impl Default for MirrorMaterial {
+    fn default() -> Self {
+        Self {
+            tint: [0.95, 0.95, 0.97],
+            reflectance: 0.9,
+            roughness: 0.0,
+            dirt: None,
+            scratches: None,
+        }
+    }
+}
+
+impl MirrorMaterial {
+    // Lets the view through untouched, e.g. for portals.
+    pub fn clear() -> Self {
+        Self {
+            tint: [1.0, 1.0, 1.0],
+            reflectance: 1.0,
+            ..Default::default()
+        }
+    }
+
+    pub fn uniform(&self) -> MirrorMaterialUniform {
+        let strength = |map: &Option<(Rc<Texture>, f32)>| map.as_ref().map_or(0.0, |m| m.1);
+        MirrorMaterialUniform::new(
+            self.tint,
+            self.reflectance,
+            self.roughness,
+            strength(&self.dirt),
+            strength(&self.scratches),
+        )
+    }
+
+    // Absent maps are bound as a 1x1 black texture.
+    pub fn create_bind_group(
+        &self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+    ) -> anyhow::Result<wgpu::BindGroup> {
+        let blank = Texture::from_color(device, queue, [0, 0, 0, 255])?;
+        let dirt = self.dirt.as_ref().map_or(&blank, |m| &m.0);
+        let scratches = self.scratches.as_ref().map_or(&blank, |m| &m.0);
+        Ok(self.uniform().create_bind_group(device, dirt, scratches))
+    }
+}
+
+// Where a mirror goes and what it looks like.
+pub struct MirrorDesc {
+    pub shape: Rc<MirrorShape>,
+    pub instance: Instance,
+    pub scale: f32,
+    pub material: MirrorMaterial,
+    // Set for a portal (see `portal::Portal`): `instance` is the entry, this the exit.
+    pub exit: Option<Instance>,
+}
+
+// Far side of a portal: where its virtual camera looks out, and the plane it is clipped at.
+struct PortalExit {
+    instance: Instance,
+    plane_uniform: MirrorPlaneUniform,
+    plane_buffer: wgpu::Buffer,
+    plane_bind_group: wgpu::BindGroup,
+}
+
+// Offscreen target of one mirror for `MirrorTechnique::RenderTexture`.
+pub struct ReflectionTexture {
+    pub color: Texture,
+    pub depth_stencil: StencilTexture,
+    pub bind_group: wgpu::BindGroup,
+}
+
+impl ReflectionTexture {
+    pub fn new(device: &wgpu::Device, config: &wgpu::SurfaceConfiguration) -> Self {
+        let color = Texture::create_render_target(device, config, "reflection_texture");
+        let depth_stencil =
+            StencilTexture::create_stencil_texture(device, config, "reflection_depth", 1, false);
+        let (_, bind_group) = color.bind_group_for_texture(device);
+
+        Self {
+            color,
+            depth_stencil,
+            bind_group,
+        }
+    }
+}
+
+// Reduced-resolution framebuffer for `MirrorTechnique::Stencil` below
+// `ReflectionQuality::Full`: same passes, same MSAA (through a transient target of the
+// render graph), resolved into `color` for upsampling.
+pub struct ReducedReflection {
+    pub color: Texture,
+    pub depth_stencil: StencilTexture,
+    pub bind_group: wgpu::BindGroup,
+}
+
+impl ReducedReflection {
+    pub fn new(
+        device: &wgpu::Device,
+        config: &wgpu::SurfaceConfiguration,
+        quality: ReflectionQuality,
+        sample_count: u32,
+    ) -> Self {
+        let config = quality.scaled_config(config);
+        let color = Texture::create_render_target(device, &config, "reduced_reflection");
+        let depth_stencil = StencilTexture::create_stencil_texture(
+            device,
+            &config,
+            "reduced_reflection_depth",
+            sample_count,
+            false,
+        );
+        let (_, bind_group) = color.bind_group_for_texture(device);
+
+        Self {
+            color,
+            depth_stencil,
+            bind_group,
+        }
+    }
+}
+
+// Outline of a mirror, loaded from a planar mesh. Mirrors of the same shape share it.
+pub struct MirrorShape {
+    pub mesh: Mesh,
+    // Mesh-space plane: normal on the counter-clockwise (front) side, point on the plane.
+    pub normal: Vector3<f32>,
+    pub point: Point3<f32>,
+    // Bounding sphere around `point`, for visibility tests.
+    pub radius: f32,
+}
+
+impl MirrorShape {
+    // Any OBJ file under res/, e.g. "models/mirror-oval.obj". Only the first object is used.
+    pub async fn load(
+        file_name: &str,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        layout: &wgpu::BindGroupLayout,
+    ) -> anyhow::Result<Rc<Self>> {
+        let model = resources::load_model(file_name, device, queue, layout).await?;
+        let mesh = model
+            .meshes
+            .into_iter()
+            .next()
+            .with_context(|| format!("mirror mesh {file_name} has no geometry"))?;
+        let shape =
+            Self::from_mesh(mesh).with_context(|| format!("invalid mirror mesh {file_name}"))?;
+        Ok(Rc::new(shape))
+    }
+
+    pub fn from_mesh(mesh: Mesh) -> anyhow::Result<Self> {
+        let (normal, point) = mesh_plane(&mesh.positions, &mesh.indices)?;
+        let radius = mesh
+            .positions
+            .iter()
+            .map(|&p| Point3::from(p).distance(point))
+            .fold(0.0, f32::max);
+        Ok(Self {
+            mesh,
+            normal,
+            point,
+            radius,
+        })
+    }
+}
+
+// Plane through the centroid, facing the side the triangles wind counter-clockwise on
+// (sum of the triangle area vectors, Newell's method).
+fn mesh_plane(
+    positions: &[[f32; 3]],
+    indices: &[u32],
+) -> anyhow::Result<(Vector3<f32>, Point3<f32>)> {
+    let points: Vec<Point3<f32>> = positions.iter().map(|&p| p.into()).collect();
+    if points.len() < 3 {
+        anyhow::bail!("a mirror needs at least 3 vertices, found {}", points.len());
+    }
+
+    let area = indices
+        .chunks_exact(3)
+        .fold(Vector3::new(0.0, 0.0, 0.0), |area, triangle| {
+            let [a, b, c] = [0, 1, 2].map(|i| points[triangle[i] as usize]);
+            area + (b - a).cross(c - a)
+        });
+    if area.magnitude2() <= f32::EPSILON {
+        anyhow::bail!(
+            "the triangles cancel out (closed or degenerate mesh), the normal is undefined"
+        );
+    }
+    let normal = area.normalize();
+
+    let centroid = Point3::centroid(&points);
+    let radius = points
+        .iter()
+        .map(|p| p.distance(centroid))
+        .fold(0.0, f32::max);
+    let tolerance = MIRROR_PLANARITY_TOLERANCE * radius;
+
+    let (vertex, offset) = points
+        .iter()
+        .map(|p| normal.dot(p - centroid))
+        .enumerate()
+        .max_by(|(_, a), (_, b)| a.abs().total_cmp(&b.abs()))
+        .unwrap_or_default();
+    if offset.abs() > tolerance {
+        anyhow::bail!(
+            "the mesh is not planar: vertex {} is {:.4} off the plane (tolerance {:.4})",
+            vertex,
+            offset.abs(),
+            tolerance
+        );
+    }
+
+    Ok((normal, centroid))
+}
+
+pub struct Mirror {
+    pub shape: Rc<MirrorShape>,
+    pub instance: Instance,
+    pub scale: f32,
+    pub stencil_reference: u32,
+    pub instance_buffer: wgpu::Buffer,
+    pub plane_uniform: MirrorPlaneUniform,
+    pub plane_buffer: wgpu::Buffer,
+    pub plane_bind_group: wgpu::BindGroup,
+    pub material: MirrorMaterial,
+    pub material_bind_group: wgpu::BindGroup,
+    exit: Option<PortalExit>,
+    pub camera_reflected_buffer: wgpu::Buffer,
+    pub camera_reflected_bind_group: wgpu::BindGroup,
+}
+
+impl Mirror {
+    pub fn new(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        desc: MirrorDesc,
+        stencil_reference: u32,
+    ) -> anyhow::Result<Self> {
+        let MirrorDesc {
+            shape,
+            instance,
+            scale,
+            material,
+            exit,
+        } = desc;
+        let instance_buffer =
+            create_instance_buffer(device, &[instance.to_raw_with_uniform_scale(scale)]);
+
+        // / M I R R O R  P L A N E  U N I F O R M
+        let plane_uniform =
+            MirrorPlaneUniform::new(&plane_transform(&shape, &instance, scale), shape.normal);
+        let plane_buffer = plane_uniform.mirror_plane_buffer(device);
+        let (_, plane_bind_group) =
+            MirrorPlaneUniform::create_bind_group_layout(device, &plane_buffer);
+
+        // / P O R T A L  E X I T
+        let exit = exit.map(|instance| {
+            let plane_uniform =
+                MirrorPlaneUniform::new(&plane_transform(&shape, &instance, scale), shape.normal);
+            let plane_buffer = plane_uniform.mirror_plane_buffer(device);
+            let (_, plane_bind_group) =
+                MirrorPlaneUniform::create_bind_group_layout(device, &plane_buffer);
+            PortalExit {
+                instance,
+                plane_uniform,
+                plane_buffer,
+                plane_bind_group,
+            }
+        });
+
+        // / M A T E R I A L
+        let material_bind_group = material.create_bind_group(device, queue)?;
+
+        // / R E F L E C T E D  C A M E R A
+        let camera_reflected_buffer = create_camera_reflected_buffer(&CameraUniform::new(), device);
+        let (_, camera_reflected_bind_group) =
+            bind_group_for_camera_uniform(&camera_reflected_buffer, device);
+
+        Ok(Self {
+            shape,
+            instance,
+            scale,
+            stencil_reference,
+            instance_buffer,
+            plane_uniform,
+            plane_buffer,
+            plane_bind_group,
+            material,
+            material_bind_group,
+            exit,
+            camera_reflected_buffer,
+            camera_reflected_bind_group,
+        })
+    }
+
+    // One mirror per description. Each one gets its own range of stencil values.
+    pub fn build_mirrors(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        descs: Vec<MirrorDesc>,
+    ) -> anyhow::Result<Vec<Mirror>> {
+        if descs.len() > MAX_MIRRORS {
+            anyhow::bail!(
+                "{} mirrors requested, the stencil buffer supports at most {}",
+                descs.len(),
+                MAX_MIRRORS
+            );
+        }
+
+        descs
+            .into_iter()
+            .enumerate()
+            .map(|(i, desc)| Mirror::new(device, queue, desc, i as u32 * MAX_REFLECTION_DEPTH + 1))
+            .collect()
+    }
+
+    pub fn is_portal(&self) -> bool {
+        self.exit.is_some()
+    }
+
+    // Reflection matrix, or the entry -> exit transform of a portal.
+    pub fn reflection(&self) -> cgmath::Matrix4<f32> {
+        match &self.exit {
+            None => build_reflection_matrix(&self.plane_transform(), self.shape.normal),
+            Some(exit) => portal_view_transform(
+                self.plane_transform(),
+                plane_transform(&self.shape, &exit.instance, self.scale),
+            ),
+        }
+    }
+
+    // World-space plane (normal, -normal . point) the virtual camera is clipped at,
+    // positive on the side it sees: the mirror itself, or the exit of a portal.
+    pub fn clip_plane(&self) -> cgmath::Vector4<f32> {
+        let instance = self
+            .exit
+            .as_ref()
+            .map_or(&self.instance, |exit| &exit.instance);
+        let transform = plane_transform(&self.shape, instance, self.scale);
+        let normal = normal_from_transform(&transform, self.shape.normal);
+        let point = point_from_transform(&transform);
+        normal.extend(-normal.dot(point.to_vec()))
+    }
+
+    fn plane_transform(&self) -> cgmath::Matrix4<f32> {
+        plane_transform(&self.shape, &self.instance, self.scale)
+    }
+
+    // `MirrorPlaneUniform` of `clip_plane`, for the reflected scene.
+    pub fn clip_plane_bind_group(&self) -> &wgpu::BindGroup {
+        self.exit
+            .as_ref()
+            .map_or(&self.plane_bind_group, |exit| &exit.plane_bind_group)
+    }
+
+    // The uniform behind `clip_plane_bind_group`, for nested reflections to copy.
+    fn clip_plane_uniform(&self) -> MirrorPlaneUniform {
+        self.exit
+            .as_ref()
+            .map_or(self.plane_uniform, |exit| exit.plane_uniform)
+    }
+
+    // Both layers of the stencil surface, camera at group 0 already set.
+    pub fn draw_surface<'a>(
+        &'a self,
+        pass: &mut wgpu::RenderPass<'a>,
+        reflection_pipeline: &'a wgpu::RenderPipeline,
+        coat_pipeline: &'a wgpu::RenderPipeline,
+    ) {
+        pass.set_bind_group(1, &self.material_bind_group, &[]);
+        pass.set_bind_group(2, &self.plane_bind_group, &[]);
+        pass.set_pipeline(reflection_pipeline);
+        self.draw(pass);
+        pass.set_pipeline(coat_pipeline);
+        self.draw(pass);
+    }
+
+    // Mirror mesh at its instance, vertex buffer 1 being the instance slot.
+    pub fn draw<'a>(&'a self, pass: &mut wgpu::RenderPass<'a>) {
+        pass.set_vertex_buffer(1, self.instance_buffer.slice(..));
+        pass.draw_mesh(&self.shape.mesh);
+    }
+
+    fn reflected_camera(
+        &self,
+        camera: &Camera,
+        reflection: cgmath::Matrix4<f32>,
+        clipping: MirrorClipping,
+    ) -> (CameraUniform, bool) {
+        use cgmath::Transform;
+
+        let clip_plane = (clipping == MirrorClipping::Oblique).then(|| self.clip_plane());
+        // The eye as seen through the mirrors, for view-dependent shading
+        let eye = reflection
+            .invert()
+            .map_or(camera.eye, |inverse| inverse.transform_point(camera.eye));
+        let (view_proj, clipped) = camera.build_reflected_camera(reflection, clip_plane);
+        (CameraUniform::from_view_proj(view_proj, eye), clipped)
+    }
+
+    // True when `point` is on the reflective side of the mirror plane.
+    pub fn faces(&self, point: cgmath::Point3<f32>) -> bool {
+        let transform = self.plane_transform();
+        let normal = normal_from_transform(&transform, self.shape.normal);
+        normal.dot(point - point_from_transform(&transform)) > 0.0
+    }
+
+    // CPU culling: bounds inside the view frustum. Whether it reflects anything is up to
+    // `faces`; seen from behind, only its back is drawn.
+    pub fn in_view(&self, frustum: &Frustum) -> bool {
+        let center = point_from_transform(&self.plane_transform());
+        frustum.intersects_sphere(center, self.shape.radius * self.scale)
+    }
+
+    // Write the reflected camera and the mirror plane for this frame.
+    pub fn update(&mut self, queue: &wgpu::Queue, camera: &Camera, clipping: MirrorClipping) {
+        let (reflected_camera, clipped) =
+            self.reflected_camera(camera, self.reflection(), clipping);
+        // Oblique clipping falls back to the discard test when the near plane can't be moved
+        let clip_discard = !clipped;
+        self.plane_uniform.set_clip_discard(clip_discard);
+
+        queue.write_buffer(
+            &self.camera_reflected_buffer,
+            0,
+            bytemuck::cast_slice(&[reflected_camera]),
+        );
+        queue.write_buffer(
+            &self.plane_buffer,
+            0,
+            bytemuck::cast_slice(&[self.plane_uniform]),
+        );
+        if let Some(exit) = &mut self.exit {
+            exit.plane_uniform.set_clip_discard(clip_discard);
+            queue.write_buffer(
+                &exit.plane_buffer,
+                0,
+                bytemuck::cast_slice(&[exit.plane_uniform]),
+            );
+        }
+    }
+}
+
+// Instance transform moved onto the plane of the mesh, so its origin lies on the mirror.
+fn plane_transform(shape: &MirrorShape, instance: &Instance, scale: f32) -> cgmath::Matrix4<f32> {
+    instance.transform()
+        * cgmath::Matrix4::from_scale(scale)
+        * cgmath::Matrix4::from_translation(shape.point.to_vec())
+}
+
+//
+// Mirror-in-mirror recursion
+
+// A mirror seen through one or more parent mirrors.
+pub struct ReflectionNode {
+    pub mirror: usize,
+    // Stencil value inside this mirror's region: parent value + 1.
+    pub stencil_reference: u32,
+    // Parent reflections composed with this mirror's own reflection.
+    pub reflection: cgmath::Matrix4<f32>,
+    // Slot of the reflected camera in the `ReflectedCameraPool`.
+    pub camera_slot: usize,
+    pub children: Vec<ReflectionNode>,
+}
+
+// Nested mirrors visible inside `parent`, at most `remaining` bounces deeper and no more
+// than `MAX_REFLECTION_NODES` slots in all.
+pub fn build_reflection_tree(
+    mirrors: &[Mirror],
+    camera: &Camera,
+    parent: usize,
+    parent_reflection: cgmath::Matrix4<f32>,
+    parent_stencil: u32,
+    remaining: u32,
+    next_slot: &mut usize,
+) -> Vec<ReflectionNode> {
+    use cgmath::Transform;
+
+    if remaining == 0 {
+        return Vec::new();
+    }
+
+    // Where the eye sits in the world seen through the parent mirrors.
+    let virtual_eye = match parent_reflection.invert() {
+        Some(inverse) => inverse.transform_point(camera.eye),
+        None => return Vec::new(),
+    };
+
+    // What the reflected camera of the parent takes in
+    let frustum = Frustum::from_view_proj(camera.build_reflected_camera(parent_reflection, None).0);
+
+    let mut nodes = Vec::new();
+    for (i, mirror) in mirrors.iter().enumerate() {
+        // A flat mirror never sees itself, nor a mirror seen from behind.
+        // A portal can: its entry may show up again beyond its exit.
+        if (i == parent && !mirror.is_portal())
+            || !mirror.faces(virtual_eye)
+            || !mirror.in_view(&frustum)
+        {
+            continue;
+        }
+        if *next_slot == MAX_REFLECTION_NODES {
+            break;
+        }
+
+        let reflection = parent_reflection * mirror.reflection();
+        let camera_slot = *next_slot;
+        *next_slot += 1;
+
+        let children = build_reflection_tree(
+            mirrors,
+            camera,
+            i,
+            reflection,
+            parent_stencil + 1,
+            remaining - 1,
+            next_slot,
+        );
+
+        nodes.push(ReflectionNode {
+            mirror: i,
+            stencil_reference: parent_stencil + 1,
+            reflection,
+            camera_slot,
+            children,
+        });
+    }
+    nodes
+}
+
+// Reflected cameras for the nested levels, one per node of this frame's trees, reused
+// from frame to frame.
+#[derive(Default)]
+pub struct ReflectedCameraPool {
+    cameras: Vec<ReflectedCameraSlot>,
+    // The trees filled all MAX_REFLECTION_NODES slots last frame
+    at_budget: bool,
+}
+
+impl ReflectedCameraPool {
+    // Grows or shrinks to `count` cameras.
+    pub fn resize(&mut self, device: &wgpu::Device, count: usize) {
+        while self.cameras.len() < count {
+            let camera_buffer = create_camera_reflected_buffer(&CameraUniform::new(), device);
+            let (_, camera_bind_group) = bind_group_for_camera_uniform(&camera_buffer, device);
+            let plane_buffer = MirrorPlaneUniform::zeroed().mirror_plane_buffer(device);
+            let (_, plane_bind_group) =
+                MirrorPlaneUniform::create_bind_group_layout(device, &plane_buffer);
+            self.cameras.push(ReflectedCameraSlot {
+                camera_buffer,
+                camera_bind_group,
+                plane_buffer,
+                plane_bind_group,
+            });
+        }
+        self.cameras.truncate(count);
+
+        let at_budget = count >= MAX_REFLECTION_NODES;
+        if at_budget && !self.at_budget {
+            log::warn!(
+                "nested mirrors: {MAX_REFLECTION_NODES} reflections drawn, deeper ones dropped"
+            );
+        }
+        self.at_budget = at_budget;
+    }
+
+    pub fn write(
+        &self,
+        queue: &wgpu::Queue,
+        mirrors: &[Mirror],
+        node: &ReflectionNode,
+        camera: &Camera,
+        clipping: MirrorClipping,
+    ) {
+        // Clipped against the nested mirror itself: the world behind it stays hidden. When
+        // this node's near plane can't be moved, its own plane uniform turns on the discard.
+        let mirror = &mirrors[node.mirror];
+        let slot = &self.cameras[node.camera_slot];
+        let (reflected_camera, clipped) =
+            mirror.reflected_camera(camera, node.reflection, clipping);
+        let mut plane_uniform = mirror.clip_plane_uniform();
+        plane_uniform.set_clip_discard(!clipped);
+
+        queue.write_buffer(
+            &slot.camera_buffer,
+            0,
+            bytemuck::cast_slice(&[reflected_camera]),
+        );
+        queue.write_buffer(
+            &slot.plane_buffer,
+            0,
+            bytemuck::cast_slice(&[plane_uniform]),
+        );
+        for child in &node.children {
+            self.write(queue, mirrors, child, camera, clipping);
+        }
+    }
+
+    pub fn bind_group(&self, slot: usize) -> &wgpu::BindGroup {
+        &self.cameras[slot].camera_bind_group
+    }
+
+    pub fn plane_bind_group(&self, slot: usize) -> &wgpu::BindGroup {
+        &self.cameras[slot].plane_bind_group
+    }
+}
+
+// One nested reflection: its camera and the clip plane it draws against.
+struct ReflectedCameraSlot {
+    camera_buffer: wgpu::Buffer,
+    camera_bind_group: wgpu::BindGroup,
+    plane_buffer: wgpu::Buffer,
+    plane_bind_group: wgpu::BindGroup,
+}
@@ -53,6 +53,9 @@ pub struct Mesh {
     pub index_buffer: wgpu::Buffer,
     pub num_elements: u32,
     pub material: usize,
+    // CPU copy of the geometry, e.g. to derive the plane of a mirror mesh
+    pub positions: Vec<[f32; 3]>,
+    pub indices: Vec<u32>,
 }
 
 pub struct Model {
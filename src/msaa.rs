@@ -0,0 +1,65 @@
+use crate::{ssr::NORMAL_FORMAT, texture::Texture};
+
+pub const SAMPLE_COUNTS: [u32; 4] = [1, 2, 4, 8];
+
+// Asked for when nothing else is: 4 is the one count WebGPU guarantees.
+pub const DEFAULT_SAMPLE_COUNT: u32 = 4;
+
+// Sample counts every multisampled target of the scene can use: the surface format (which
+// must also resolve), the depth-stencil and the SSR normals. Always has 1.
+pub fn supported_sample_counts(
+    adapter: &wgpu::Adapter,
+    device: &wgpu::Device,
+    surface_format: wgpu::TextureFormat,
+) -> Vec<u32> {
+    let flags = [surface_format, Texture::DEPTH_STENCIL_FORMAT, NORMAL_FORMAT]
+        .map(|format| format_features(adapter, device, format).flags);
+    let resolves = flags[0].contains(wgpu::TextureFormatFeatureFlags::MULTISAMPLE_RESOLVE);
+
+    SAMPLE_COUNTS
+        .into_iter()
+        .filter(|&count| {
+            count == 1
+                || (resolves
+                    && flags
+                        .iter()
+                        .all(|flags| flags.sample_count_supported(count)))
+        })
+        .collect()
+}
+
+// Whether a multisampled depth-stencil can also be sampled, as the SSR pass does with MSAA
+// on. wgpu's GL backend (WebGL2, llvmpipe) fails to create one, as it does any sampled
+// multisampled texture.
+pub fn samples_multisampled_depth(adapter: &wgpu::Adapter, device: &wgpu::Device) -> bool {
+    let features = format_features(adapter, device, Texture::DEPTH_STENCIL_FORMAT);
+    features
+        .allowed_usages
+        .contains(wgpu::TextureUsages::TEXTURE_BINDING)
+        && adapter.get_info().backend != wgpu::Backend::Gl
+}
+
+// `requested` when supported, else the next lower count that is.
+pub fn fallback_sample_count(supported: &[u32], requested: u32) -> u32 {
+    supported
+        .iter()
+        .copied()
+        .filter(|&count| count <= requested)
+        .max()
+        .unwrap_or(1)
+}
+
+// What the device accepts: the adapter's own features only when it was asked for them,
+// the WebGPU guarantees otherwise.
+fn format_features(
+    adapter: &wgpu::Adapter,
+    device: &wgpu::Device,
+    format: wgpu::TextureFormat,
+) -> wgpu::TextureFormatFeatures {
+    let features = device.features();
+    if features.contains(wgpu::Features::TEXTURE_ADAPTER_SPECIFIC_FORMAT_FEATURES) {
+        adapter.get_texture_format_features(format)
+    } else {
+        format.guaranteed_format_features(features)
+    }
+}
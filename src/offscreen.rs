@@ -0,0 +1,186 @@
+// / O F F S C R E E N
+// / Frames rendered into a texture instead of a window: no display needed, e.g. for tests
+// / and batch rendering on machines without a GPU (`force_fallback_adapter`).
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct OffscreenDesc {
+    pub width: u32,
+    pub height: u32,
+    // The render format, as `config.format`. sRGB unless the frame should stay linear.
+    pub format: wgpu::TextureFormat,
+    // A software adapter (lavapipe, llvmpipe) even where a GPU is available
+    pub force_fallback_adapter: bool,
+}
+
+impl OffscreenDesc {
+    pub fn new(width: u32, height: u32) -> Self {
+        Self {
+            width,
+            height,
+            format: wgpu::TextureFormat::Rgba8UnormSrgb,
+            force_fallback_adapter: false,
+        }
+    }
+
+    // The render config the scene is built for, as if a surface had been negotiated.
+    pub fn config(&self) -> wgpu::SurfaceConfiguration {
+        wgpu::SurfaceConfiguration {
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
+            format: self.format,
+            width: self.width,
+            height: self.height,
+            present_mode: wgpu::PresentMode::Fifo,
+            alpha_mode: wgpu::CompositeAlphaMode::Opaque,
+            view_formats: vec![],
+            desired_maximum_frame_latency: 1,
+        }
+    }
+}
+
+// Stands in for the surface texture; holds the last frame until the next one.
+pub struct OffscreenTarget {
+    pub texture: wgpu::Texture,
+}
+
+impl OffscreenTarget {
+    pub fn new(device: &wgpu::Device, config: &wgpu::SurfaceConfiguration) -> Self {
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("offscreen_frame"),
+            size: wgpu::Extent3d {
+                width: config.width.max(1),
+                height: config.height.max(1),
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: config.format,
+            // COPY_SRC for reading the frame back
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::COPY_SRC,
+            view_formats: &[],
+        });
+        Self { texture }
+    }
+}
+
+// Copies an 8-bit RGBA or BGRA texture (with COPY_SRC) into an RGBA image, top row first.
+pub async fn read_rgba(
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    texture: &wgpu::Texture,
+) -> anyhow::Result<image::RgbaImage> {
+    let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+        label: Some("Readback Encoder"),
+    });
+    let readback = Readback::new(device, &mut encoder, texture)?;
+    queue.submit(std::iter::once(encoder.finish()));
+    readback.read(device).await
+}
+
+// A texture copied into a mappable buffer: recorded with the frame, mapped once the frame
+// is submitted, read once mapped.
+pub struct Readback {
+    buffer: wgpu::Buffer,
+    width: u32,
+    height: u32,
+    padded_row_bytes: u32,
+    bgra: bool,
+}
+
+impl Readback {
+    // Records the copy of an 8-bit RGBA or BGRA texture (with COPY_SRC) into `encoder`.
+    pub fn new(
+        device: &wgpu::Device,
+        encoder: &mut wgpu::CommandEncoder,
+        texture: &wgpu::Texture,
+    ) -> anyhow::Result<Self> {
+        use wgpu::TextureFormat::*;
+        let bgra = match texture.format() {
+            Rgba8Unorm | Rgba8UnormSrgb => false,
+            Bgra8Unorm | Bgra8UnormSrgb => true,
+            format => anyhow::bail!("can't read back {format:?}: 8-bit RGBA or BGRA only"),
+        };
+        if !texture.usage().contains(wgpu::TextureUsages::COPY_SRC) {
+            anyhow::bail!("can't read back a texture without COPY_SRC");
+        }
+
+        // Rows of a texture copy start every 256 bytes
+        let (width, height) = (texture.width(), texture.height());
+        let padded_row_bytes = (width * 4).div_ceil(wgpu::COPY_BYTES_PER_ROW_ALIGNMENT)
+            * wgpu::COPY_BYTES_PER_ROW_ALIGNMENT;
+
+        let buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("readback_buffer"),
+            size: (padded_row_bytes * height) as u64,
+            usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
+            mapped_at_creation: false,
+        });
+        encoder.copy_texture_to_buffer(
+            texture.as_image_copy(),
+            wgpu::TexelCopyBufferInfo {
+                buffer: &buffer,
+                layout: wgpu::TexelCopyBufferLayout {
+                    offset: 0,
+                    bytes_per_row: Some(padded_row_bytes),
+                    rows_per_image: Some(height),
+                },
+            },
+            texture.size(),
+        );
+
+        Ok(Self {
+            buffer,
+            width,
+            height,
+            padded_row_bytes,
+            bgra,
+        })
+    }
+
+    // Once the copy is submitted. `on_mapped` runs on a device poll (native, WebGL) or by
+    // itself (WebGPU).
+    pub fn map(
+        &self,
+        on_mapped: impl FnOnce(Result<(), wgpu::BufferAsyncError>) + wgpu::WasmNotSend + 'static,
+    ) {
+        self.buffer
+            .slice(..)
+            .map_async(wgpu::MapMode::Read, on_mapped);
+    }
+
+    // Once the copy is submitted: maps, waits for the GPU and reads.
+    pub async fn read(&self, device: &wgpu::Device) -> anyhow::Result<image::RgbaImage> {
+        let (sender, receiver) = futures_channel::oneshot::channel();
+        self.map(move |result| {
+            let _ = sender.send(result);
+        });
+        // Native and WebGL map on poll; WebGPU resolves the callback by itself
+        device.poll(wgpu::PollType::wait_indefinitely())?;
+        receiver.await??;
+        self.to_rgba()
+    }
+
+    // Once mapped: the pixels in RGBA, top row first, without the row padding.
+    pub fn to_rgba(&self) -> anyhow::Result<image::RgbaImage> {
+        let row_bytes = (self.width * 4) as usize;
+        let mut pixels = Vec::with_capacity(row_bytes * self.height as usize);
+        for row in self
+            .buffer
+            .slice(..)
+            .get_mapped_range()
+            .chunks_exact(self.padded_row_bytes as usize)
+        {
+            pixels.extend_from_slice(&row[..row_bytes]);
+        }
+        self.buffer.unmap();
+        if self.bgra {
+            pixels
+                .chunks_exact_mut(4)
+                .for_each(|pixel| pixel.swap(0, 2));
+        }
+
+        let (width, height) = (self.width, self.height);
+        image::RgbaImage::from_raw(width, height, pixels)
+            .ok_or_else(|| anyhow::anyhow!("readback of {width}x{height} came back short"))
+    }
+}
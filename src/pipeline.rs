@@ -1,330 +1,656 @@
-use anyhow::*;
-
-use crate::vertex::Vertex as PrimitiveVertex;
 use crate::{
+    debugger::Pipeline as DebugPipeline,
+    environment::EnvironmentMap,
+    extra::{MirrorMaterialUniform, MirrorPlaneUniform, SurfaceUniform},
+    mirror::SurfaceLayer,
     model::{ModelVertex, Vertex},
+    pipeline_desc::{shader, PipelineCache, PipelineDesc},
+    ssr::{ScreenSpaceReflections, NORMAL_FORMAT},
+    stats_overlay::StatsOverlay,
     vertex::InstanceRaw,
+    water::Water,
 };
-pub struct Pipeline {
-    pub pipeline: wgpu::RenderPipeline,
+
+// The pipelines of the demo, as descriptions for the `PipelineCache`.
+pub struct Pipeline;
+
+fn mesh_buffers() -> [wgpu::VertexBufferLayout<'static>; 2] {
+    [ModelVertex::desc(), InstanceRaw::desc()]
 }
 
 impl Pipeline {
     pub fn build_render_pipeline(
-        device: &wgpu::Device,
         config: &wgpu::SurfaceConfiguration,
         sample_count: u32,
         texture_bind_group_layout: &wgpu::BindGroupLayout,
         camera_uniform_bind_group_layout: &wgpu::BindGroupLayout,
         spin_uniform_bind_group_layout: &wgpu::BindGroupLayout,
-        mirror_plane_uniform_bind_group_layout: &wgpu::BindGroupLayout, // TODO REVIEW currently not used. Is this needed. test with object behind mirror
-    ) -> Result<Pipeline> {
-        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
-            label: Some("Shader"),
-            source: wgpu::ShaderSource::Wgsl(include_str!("shaders/shader.wgsl").into()),
-        });
-
-        let render_pipeline_layout =
-            device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
-                label: Some("Render Pipeline Layout"),
-                bind_group_layouts: &[
-                    texture_bind_group_layout,
-                    camera_uniform_bind_group_layout,
-                    spin_uniform_bind_group_layout,
-                    mirror_plane_uniform_bind_group_layout,
-                ],
-                push_constant_ranges: &[],
-            });
+        surface_uniform_bind_group_layout: &wgpu::BindGroupLayout,
+    ) -> PipelineDesc {
+        PipelineDesc::new("Render Pipeline", shader!("Shader", "shader.wgsl"))
+            .fragment(Some("fs_scene"))
+            .vertex_buffers(&mesh_buffers())
+            .bind_group_layouts(&[
+                texture_bind_group_layout,
+                camera_uniform_bind_group_layout,
+                spin_uniform_bind_group_layout,
+                surface_uniform_bind_group_layout,
+            ])
+            .target(config.format, Some(wgpu::BlendState::REPLACE))
+            // Normal and reflectivity for screen-space reflections
+            .target(NORMAL_FORMAT, None)
+            // No stencil test: the mirrors already wrote their depth, so only
+            // what is in front of a mirror gets drawn over its reflection.
+            .depth(true, wgpu::CompareFunction::Less)
+            .cull(Some(wgpu::Face::Back))
+            .sample_count(sample_count)
+    }
 
-        //Pipeline
+    pub fn mask_render_pipeline(
+        camera_uniform_bind_group_layout: &wgpu::BindGroupLayout,
+        sample_count: u32,
+    ) -> PipelineDesc {
+        PipelineDesc::new("Mask_Render_Pipeline", shader!("stencil", "stencil.wgsl"))
+            .fragment(None)
+            .vertex_buffers(&mesh_buffers())
+            .bind_group_layouts(&[camera_uniform_bind_group_layout])
+            .stencil(
+                wgpu::CompareFunction::Always,
+                wgpu::StencilOperation::Replace,
+            )
+            .sample_count(sample_count)
+    }
 
-        let render_pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
-            label: Some("Render Pipeline"),
-            layout: Some(&render_pipeline_layout),
-            vertex: wgpu::VertexState {
-                module: &shader,
-                entry_point: Some("vs_main"), // 1.
-                buffers: &[ModelVertex::desc(), InstanceRaw::desc()], // 2.
-                compilation_options: wgpu::PipelineCompilationOptions::default(),
-            },
-            fragment: Some(wgpu::FragmentState {
-                // 3.
-                module: &shader,
-                entry_point: Some("fs_main"),
-                targets: &[Some(wgpu::ColorTargetState {
-                    // 4.
-                    format: config.format,
-                    blend: Some(wgpu::BlendState::REPLACE),
-                    write_mask: wgpu::ColorWrites::ALL,
-                })],
-                compilation_options: wgpu::PipelineCompilationOptions::default(),
-            }),
-            primitive: wgpu::PrimitiveState {
-                topology: wgpu::PrimitiveTopology::TriangleList, // 1.
-                strip_index_format: None,
-                front_face: wgpu::FrontFace::Ccw, // 2.
-                cull_mode: Some(wgpu::Face::Back),
-                // Setting this to anything other than Fill requires Features::NON_FILL_POLYGON_MODE
-                polygon_mode: wgpu::PolygonMode::Fill,
-                // Requires Features::DEPTH_CLIP_CONTROL
-                unclipped_depth: false,
-                // Requires Features::CONSERVATIVE_RASTERIZATION
-                conservative: false,
-            },
-            depth_stencil: Some(wgpu::DepthStencilState {
-                format: wgpu::TextureFormat::Depth24PlusStencil8,
-                depth_write_enabled: true,
-                depth_compare: wgpu::CompareFunction::Less,
-                //stencil: wgpu::StencilState::default(),
-                stencil: wgpu::StencilState {
-                    front: wgpu::StencilFaceState {
-                        compare: wgpu::CompareFunction::Less,
-                        fail_op: wgpu::StencilOperation::Keep,
-                        depth_fail_op: wgpu::StencilOperation::Keep,
-                        pass_op: wgpu::StencilOperation::Keep,
-                    },
-                    back: wgpu::StencilFaceState::IGNORE,
-                    read_mask: 0xFF,
-                    write_mask: 0x00,
-                },
+    pub fn reflection_render_pipeline(
+        config: &wgpu::SurfaceConfiguration,
+        texture_bind_group_layout: &wgpu::BindGroupLayout,
+        camera_uniform_bind_group_layout: &wgpu::BindGroupLayout,
+        spin_uniform_bind_group_layout: &wgpu::BindGroupLayout,
+        mirror_plane_uniform_bind_group_layout: &wgpu::BindGroupLayout,
+        sample_count: u32,
+    ) -> PipelineDesc {
+        PipelineDesc::new(
+            "Reflected_scene_pipeline",
+            shader!("mirror reflection", "shader.wgsl").define("MIRROR_CLIP"),
+        )
+        .vertex_buffers(&mesh_buffers())
+        .bind_group_layouts(&[
+            texture_bind_group_layout,
+            camera_uniform_bind_group_layout,
+            spin_uniform_bind_group_layout,
+            mirror_plane_uniform_bind_group_layout,
+        ])
+        .target(config.format, Some(wgpu::BlendState::REPLACE))
+        .depth(true, wgpu::CompareFunction::Less)
+        .stencil(wgpu::CompareFunction::Equal, wgpu::StencilOperation::Keep)
+        // important: reflection flips winding
+        .cull(None)
+        .sample_count(sample_count)
+    }
 
-                bias: wgpu::DepthBiasState::default(),
-            }),
-            multisample: wgpu::MultisampleState {
-                count: sample_count,              // 2.
-                mask: !0,                         // 3.
-                alpha_to_coverage_enabled: false, // 4.
-            },
-            multiview: None, // 5.
-            cache: None,     // 6.
-        });
-        Ok(Self {
-            pipeline: render_pipeline,
-        })
+    pub fn mirror_surface_render_pipeline(
+        config: &wgpu::SurfaceConfiguration,
+        camera_uniform_bind_group_layout: &wgpu::BindGroupLayout,
+        mirror_material_bind_group_layout: &wgpu::BindGroupLayout,
+        mirror_plane_bind_group_layout: &wgpu::BindGroupLayout,
+        layer: SurfaceLayer,
+        sample_count: u32,
+    ) -> PipelineDesc {
+        PipelineDesc::new(
+            "Mirror surface Render Pipeline",
+            shader!("Mirror surface", "mirror_surface.wgsl"),
+        )
+        .fragment(Some(layer.entry_point()))
+        .vertex_buffers(&mesh_buffers())
+        .bind_group_layouts(&[
+            camera_uniform_bind_group_layout,
+            mirror_material_bind_group_layout,
+            mirror_plane_bind_group_layout,
+        ])
+        .target(config.format, Some(layer.blend()))
+        // LessEqual: the reflection pass already wrote the mirror's own depth
+        .depth(false, wgpu::CompareFunction::LessEqual)
+        .sample_count(sample_count)
     }
+}
 
-    pub fn mask_render_pipeline(
-        device: &wgpu::Device,
+// /
+// / N E S T E D   M I R R O R S
+// /
+// / A mirror seen inside a mirror is drawn inside the reflection pass, so these
+// / pipelines carry a color target even when they only touch depth/stencil.
+
+impl Pipeline {
+    // Mark a nested mirror: where stencil == parent value, increment to parent + 1.
+    pub fn nested_mask_render_pipeline(
+        config: &wgpu::SurfaceConfiguration,
         camera_uniform_bind_group_layout: &wgpu::BindGroupLayout,
         sample_count: u32,
-    ) -> Result<Pipeline> {
-        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
-            label: Some("stencil"),
-            source: wgpu::ShaderSource::Wgsl(include_str!("shaders/stencil.wgsl").into()),
-        });
+    ) -> PipelineDesc {
+        PipelineDesc::new(
+            "Nested_Mask_Render_Pipeline",
+            shader!("nested stencil", "stencil.wgsl"),
+        )
+        .vertex_buffers(&mesh_buffers())
+        .bind_group_layouts(&[camera_uniform_bind_group_layout])
+        .masked_target(config.format)
+        // Reflected objects in front of the nested mirror hide it.
+        .depth(false, wgpu::CompareFunction::Less)
+        .two_sided_stencil(
+            wgpu::CompareFunction::Equal,
+            wgpu::StencilOperation::IncrementClamp,
+        )
+        .sample_count(sample_count)
+    }
 
-        let mask_pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
-            label: Some("mask_pipeline_layout"),
-            bind_group_layouts: &[camera_uniform_bind_group_layout],
-            push_constant_ranges: &[],
-        });
+    // Reset depth to the far plane where stencil == nested mirror value.
+    pub fn clear_depth_render_pipeline(
+        config: &wgpu::SurfaceConfiguration,
+        sample_count: u32,
+    ) -> PipelineDesc {
+        // fullscreen triangle, no vertex buffers
+        PipelineDesc::new(
+            "Clear_Depth_Render_Pipeline",
+            shader!("clear depth", "clear_depth.wgsl"),
+        )
+        .masked_target(config.format)
+        .depth(true, wgpu::CompareFunction::Always)
+        .stencil(wgpu::CompareFunction::Equal, wgpu::StencilOperation::Keep)
+        .sample_count(sample_count)
+    }
 
-        let mask_render_pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
-            label: Some("Mask_Render_Pipeline"),
-            layout: Some(&mask_pipeline_layout),
-            vertex: wgpu::VertexState {
-                module: &shader,
-                entry_point: Some("vs_main"), // 1.
-                buffers: &[PrimitiveVertex::desc(), InstanceRaw::desc()], // 2.
-                compilation_options: wgpu::PipelineCompilationOptions::default(),
-            },
-            fragment: None,
-            primitive: wgpu::PrimitiveState::default(),
-            depth_stencil: Some(wgpu::DepthStencilState {
-                format: wgpu::TextureFormat::Depth24PlusStencil8,
-                depth_write_enabled: false,
-                depth_compare: wgpu::CompareFunction::Always,
-                stencil: wgpu::StencilState {
-                    front: wgpu::StencilFaceState {
-                        compare: wgpu::CompareFunction::Always,
-                        fail_op: wgpu::StencilOperation::Keep,
-                        depth_fail_op: wgpu::StencilOperation::Keep,
-                        pass_op: wgpu::StencilOperation::Replace,
-                    },
-                    back: wgpu::StencilFaceState::IGNORE,
-                    read_mask: 0xFF,
-                    write_mask: 0xFF,
-                },
-                bias: wgpu::DepthBiasState::default(),
-            }),
-            multisample: wgpu::MultisampleState {
-                count: sample_count,
-                ..Default::default()
+    // Shade a nested mirror where stencil == nested value. The coat layer comes last:
+    // it writes the mirror depth and decrements the stencil back to the parent value.
+    pub fn nested_surface_render_pipeline(
+        config: &wgpu::SurfaceConfiguration,
+        camera_uniform_bind_group_layout: &wgpu::BindGroupLayout,
+        mirror_material_bind_group_layout: &wgpu::BindGroupLayout,
+        mirror_plane_bind_group_layout: &wgpu::BindGroupLayout,
+        layer: SurfaceLayer,
+        sample_count: u32,
+    ) -> PipelineDesc {
+        let restore = layer == SurfaceLayer::Coat;
+
+        PipelineDesc::new(
+            "Nested mirror surface Render Pipeline",
+            shader!("Nested mirror surface", "mirror_surface.wgsl"),
+        )
+        .fragment(Some(layer.entry_point()))
+        .vertex_buffers(&mesh_buffers())
+        .bind_group_layouts(&[
+            camera_uniform_bind_group_layout,
+            mirror_material_bind_group_layout,
+            mirror_plane_bind_group_layout,
+        ])
+        .target(config.format, Some(layer.blend()))
+        // The mirror becomes an opaque surface for the parent level.
+        .depth(restore, wgpu::CompareFunction::Always)
+        .two_sided_stencil(
+            wgpu::CompareFunction::Equal,
+            if restore {
+                wgpu::StencilOperation::DecrementClamp
+            } else {
+                wgpu::StencilOperation::Keep
             },
-            multiview: None,
-            cache: None,
-        });
+        )
+        .sample_count(sample_count)
+    }
+}
+
+// /
+// / M I R R O R   D E P T H
+// /
+
+impl Pipeline {
+    // Once its reflection is drawn, a mirror becomes an opaque surface at its own depth
+    // (seen from the real camera), so the scene behind it stays hidden.
+    pub fn mirror_depth_render_pipeline(
+        config: &wgpu::SurfaceConfiguration,
+        camera_uniform_bind_group_layout: &wgpu::BindGroupLayout,
+        sample_count: u32,
+    ) -> PipelineDesc {
+        PipelineDesc::new(
+            "Mirror_Depth_Render_Pipeline",
+            shader!("mirror depth", "stencil.wgsl"),
+        )
+        .vertex_buffers(&mesh_buffers())
+        .bind_group_layouts(&[camera_uniform_bind_group_layout])
+        .masked_target(config.format)
+        // The depth buffer holds reflected depths here, not comparable
+        .depth(true, wgpu::CompareFunction::Always)
+        .stencil(wgpu::CompareFunction::Equal, wgpu::StencilOperation::Keep)
+        .sample_count(sample_count)
+    }
 
-        Ok(Self {
-            pipeline: mask_render_pipeline,
-        })
+    // Stand-in for a mirror skipped as occluded: depth test only, so its occlusion
+    // query can tell when it shows up again.
+    pub fn occlusion_probe_render_pipeline(
+        config: &wgpu::SurfaceConfiguration,
+        camera_uniform_bind_group_layout: &wgpu::BindGroupLayout,
+        sample_count: u32,
+    ) -> PipelineDesc {
+        PipelineDesc::new(
+            "Occlusion_Probe_Render_Pipeline",
+            shader!("occlusion probe", "stencil.wgsl"),
+        )
+        .vertex_buffers(&mesh_buffers())
+        .bind_group_layouts(&[camera_uniform_bind_group_layout])
+        .masked_target(config.format)
+        .depth(false, wgpu::CompareFunction::LessEqual)
+        .sample_count(sample_count)
     }
+}
 
-    pub fn reflection_render_pipeline(
-        device: &wgpu::Device,
+// /
+// / R E N D E R   T O   T E X T U R E
+// /
+
+impl Pipeline {
+    // Reflected scene into a single-sampled offscreen texture. No stencil: the whole
+    // target belongs to one mirror.
+    pub fn reflection_texture_render_pipeline(
         config: &wgpu::SurfaceConfiguration,
         texture_bind_group_layout: &wgpu::BindGroupLayout,
         camera_uniform_bind_group_layout: &wgpu::BindGroupLayout,
         spin_uniform_bind_group_layout: &wgpu::BindGroupLayout,
         mirror_plane_uniform_bind_group_layout: &wgpu::BindGroupLayout,
-        sample_count: u32,
-    ) -> Result<Pipeline> {
-        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
-            label: Some("mirror reflection"),
-            source: wgpu::ShaderSource::Wgsl(include_str!("shaders/mirror_reflection.wgsl").into()),
-        });
+    ) -> PipelineDesc {
+        PipelineDesc::new(
+            "Reflected_texture_pipeline",
+            shader!("mirror reflection texture", "shader.wgsl").define("MIRROR_CLIP"),
+        )
+        .vertex_buffers(&mesh_buffers())
+        .bind_group_layouts(&[
+            texture_bind_group_layout,
+            camera_uniform_bind_group_layout,
+            spin_uniform_bind_group_layout,
+            mirror_plane_uniform_bind_group_layout,
+        ])
+        .target(config.format, Some(wgpu::BlendState::REPLACE))
+        .depth(true, wgpu::CompareFunction::Less)
+        // reflection flips winding
+        .cull(None)
+    }
 
-        let render_pipeline_layout =
-            device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
-                label: Some("Render Pipeline Layout"),
-                bind_group_layouts: &[
-                    texture_bind_group_layout,
-                    camera_uniform_bind_group_layout,
-                    spin_uniform_bind_group_layout,
-                    mirror_plane_uniform_bind_group_layout,
-                ],
-                push_constant_ranges: &[],
-            });
+    // Mirror surface sampling its reflection texture in projective screen space.
+    pub fn mirror_surface_textured_render_pipeline(
+        config: &wgpu::SurfaceConfiguration,
+        texture_bind_group_layout: &wgpu::BindGroupLayout,
+        camera_uniform_bind_group_layout: &wgpu::BindGroupLayout,
+        mirror_material_bind_group_layout: &wgpu::BindGroupLayout,
+        mirror_plane_bind_group_layout: &wgpu::BindGroupLayout,
+        sample_count: u32,
+    ) -> PipelineDesc {
+        PipelineDesc::new(
+            "Mirror surface textured Render Pipeline",
+            shader!("Mirror surface textured", "mirror_surface_textured.wgsl"),
+        )
+        .vertex_buffers(&mesh_buffers())
+        .bind_group_layouts(&[
+            texture_bind_group_layout,
+            camera_uniform_bind_group_layout,
+            mirror_material_bind_group_layout,
+            mirror_plane_bind_group_layout,
+        ])
+        .target(config.format, Some(wgpu::BlendState::REPLACE))
+        .depth(true, wgpu::CompareFunction::Less)
+        .sample_count(sample_count)
+    }
+}
 
-        let reflected_pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
-            label: Some("Reflected_scene_pipeline"),
-            layout: Some(&render_pipeline_layout),
-            vertex: wgpu::VertexState {
-                module: &shader,
-                entry_point: Some("vs_main"), // 1.
-                buffers: &[ModelVertex::desc(), InstanceRaw::desc()], // 2.
-                compilation_options: wgpu::PipelineCompilationOptions::default(),
-            },
-            fragment: Some(wgpu::FragmentState {
-                // 3.
-                module: &shader,
-                entry_point: Some("fs_main"),
-                targets: &[Some(wgpu::ColorTargetState {
-                    // 4.
-                    format: config.format,
-                    blend: Some(wgpu::BlendState::REPLACE),
-                    write_mask: wgpu::ColorWrites::ALL,
-                })],
-                compilation_options: wgpu::PipelineCompilationOptions::default(),
-            }),
-            depth_stencil: Some(wgpu::DepthStencilState {
-                format: wgpu::TextureFormat::Depth24PlusStencil8,
-                depth_write_enabled: true,
-                depth_compare: wgpu::CompareFunction::Less,
-                stencil: wgpu::StencilState {
-                    front: wgpu::StencilFaceState {
-                        compare: wgpu::CompareFunction::Equal,
-                        fail_op: wgpu::StencilOperation::Keep,
-                        depth_fail_op: wgpu::StencilOperation::Keep,
-                        pass_op: wgpu::StencilOperation::Keep,
-                    },
-                    back: wgpu::StencilFaceState::IGNORE,
-                    read_mask: 0xFF,
-                    write_mask: 0x00,
-                },
-                bias: wgpu::DepthBiasState::default(),
-            }),
-            primitive: wgpu::PrimitiveState {
-                front_face: wgpu::FrontFace::Ccw, //  TODO: Check and tune
-                cull_mode: None,                  // important: reflection flips winding
-                ..Default::default()
-            },
-            multiview: None,
-            cache: None,
-            multisample: wgpu::MultisampleState {
-                count: sample_count,
-                ..Default::default()
-            },
-        });
+//
+// Environment (cube map) reflections
+impl Pipeline {
+    // Scene seen from a cube map face, no stencil and no MSAA.
+    pub fn environment_face_render_pipeline(
+        config: &wgpu::SurfaceConfiguration,
+        texture_bind_group_layout: &wgpu::BindGroupLayout,
+        camera_uniform_bind_group_layout: &wgpu::BindGroupLayout,
+        spin_uniform_bind_group_layout: &wgpu::BindGroupLayout,
+    ) -> PipelineDesc {
+        PipelineDesc::new(
+            "Environment face Render Pipeline",
+            shader!("Environment face Shader", "shader.wgsl"),
+        )
+        .vertex_buffers(&mesh_buffers())
+        .bind_group_layouts(&[
+            texture_bind_group_layout,
+            camera_uniform_bind_group_layout,
+            spin_uniform_bind_group_layout,
+        ])
+        .target(config.format, Some(wgpu::BlendState::REPLACE))
+        .depth(true, wgpu::CompareFunction::Less)
+        // cube faces are left-handed, winding is flipped
+        .cull(None)
+    }
 
-        Ok(Self {
-            pipeline: reflected_pipeline,
-        })
+    // Reflective models sampling their cube map with the reflected view vector.
+    pub fn environment_render_pipeline(
+        config: &wgpu::SurfaceConfiguration,
+        environment_bind_group_layout: &wgpu::BindGroupLayout,
+        camera_uniform_bind_group_layout: &wgpu::BindGroupLayout,
+        sample_count: u32,
+    ) -> PipelineDesc {
+        PipelineDesc::new(
+            "Environment Render Pipeline",
+            shader!("Environment Shader", "environment.wgsl"),
+        )
+        .vertex_buffers(&mesh_buffers())
+        .bind_group_layouts(&[
+            environment_bind_group_layout,
+            camera_uniform_bind_group_layout,
+        ])
+        .target(config.format, Some(wgpu::BlendState::REPLACE))
+        .target(NORMAL_FORMAT, None)
+        .depth(true, wgpu::CompareFunction::Less)
+        .cull(Some(wgpu::Face::Back))
+        .sample_count(sample_count)
     }
+}
 
-    pub fn mirror_surface_render_pipeline(
-        device: &wgpu::Device,
+//
+// Planar water
+impl Pipeline {
+    // Water surface mixing its reflection and refraction textures, in the main pass.
+    pub fn water_render_pipeline(
         config: &wgpu::SurfaceConfiguration,
+        water_bind_group_layout: &wgpu::BindGroupLayout,
         camera_uniform_bind_group_layout: &wgpu::BindGroupLayout,
         sample_count: u32,
-    ) -> Result<Pipeline> {
-        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
-            label: Some("Mirror surface"),
-            source: wgpu::ShaderSource::Wgsl(include_str!("shaders/mirror_surface.wgsl").into()),
-        });
+    ) -> PipelineDesc {
+        PipelineDesc::new(
+            "Water Render Pipeline",
+            shader!("Water Shader", "water.wgsl"),
+        )
+        .vertex_buffers(&mesh_buffers())
+        .bind_group_layouts(&[water_bind_group_layout, camera_uniform_bind_group_layout])
+        .target(config.format, Some(wgpu::BlendState::REPLACE))
+        .target(NORMAL_FORMAT, None)
+        .depth(true, wgpu::CompareFunction::Less)
+        // Seen from below, the water shows the refraction only
+        .cull(None)
+        .sample_count(sample_count)
+    }
+}
 
-        let render_pipeline_layout =
-            device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
-                label: Some("Mirror surface Pipeline Layout"),
-                bind_group_layouts: &[camera_uniform_bind_group_layout],
-                push_constant_ranges: &[],
-            });
+//
+// Screen-space reflections
+impl Pipeline {
+    // Fullscreen pass from the offscreen scene color to the surface.
+    pub fn ssr_render_pipeline(
+        config: &wgpu::SurfaceConfiguration,
+        ssr_bind_group_layout: &wgpu::BindGroupLayout,
+        sample_count: u32,
+    ) -> PipelineDesc {
+        let shader = shader!("SSR Shader", "ssr.wgsl");
+        let shader = if sample_count > 1 {
+            shader.define("MSAA")
+        } else {
+            shader
+        };
 
-        //Pipeline
+        PipelineDesc::new("SSR Render Pipeline", shader)
+            .bind_group_layouts(&[ssr_bind_group_layout])
+            .target(config.format, None)
+    }
+}
 
-        let render_pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
-            label: Some("Mirror surface Render Pipeline"),
-            layout: Some(&render_pipeline_layout),
-            vertex: wgpu::VertexState {
-                module: &shader,
-                entry_point: Some("vs_main"), // 1.
-                buffers: &[PrimitiveVertex::desc(), InstanceRaw::desc()], // 2.
-                compilation_options: wgpu::PipelineCompilationOptions::default(),
-            },
-            fragment: Some(wgpu::FragmentState {
-                // 3.
-                module: &shader,
-                entry_point: Some("fs_main"),
-                targets: &[Some(wgpu::ColorTargetState {
-                    // 4.
-                    format: config.format,
-                    blend: Some(wgpu::BlendState::ALPHA_BLENDING),
-                    write_mask: wgpu::ColorWrites::ALL,
-                })],
-                compilation_options: wgpu::PipelineCompilationOptions::default(),
-            }),
-            primitive: wgpu::PrimitiveState {
-                topology: wgpu::PrimitiveTopology::TriangleList, // 1.
-                strip_index_format: None,
-                front_face: wgpu::FrontFace::Ccw, // 2.
-                //cull_mode: Some(wgpu::Face::Back),
-                cull_mode: None,
-                // Setting this to anything other than Fill requires Features::NON_FILL_POLYGON_MODE
-                polygon_mode: wgpu::PolygonMode::Fill,
-                // Requires Features::DEPTH_CLIP_CONTROL
-                unclipped_depth: false,
-                // Requires Features::CONSERVATIVE_RASTERIZATION
-                conservative: false,
-            },
-            depth_stencil: Some(wgpu::DepthStencilState {
-                format: wgpu::TextureFormat::Depth24PlusStencil8,
-                depth_write_enabled: false,
-                depth_compare: wgpu::CompareFunction::Less,
-                //stencil: wgpu::StencilState::default(),
-                stencil: wgpu::StencilState {
-                    front: wgpu::StencilFaceState {
-                        compare: wgpu::CompareFunction::Less,
-                        fail_op: wgpu::StencilOperation::Keep,
-                        depth_fail_op: wgpu::StencilOperation::Keep,
-                        pass_op: wgpu::StencilOperation::Keep,
-                    },
-                    back: wgpu::StencilFaceState::IGNORE,
-                    read_mask: 0xFF,
-                    write_mask: 0x00,
-                },
+//
+// Shader hot reload
+impl Pipeline {
+    // Warning stripes over the presented frame while a reloaded shader is broken.
+    pub fn shader_error_render_pipeline(config: &wgpu::SurfaceConfiguration) -> PipelineDesc {
+        PipelineDesc::new(
+            "Shader Error Render Pipeline",
+            shader!("Shader Error Shader", "shader_error.wgsl"),
+        )
+        .target(config.format, Some(wgpu::BlendState::ALPHA_BLENDING))
+    }
+}
 
-                bias: wgpu::DepthBiasState::default(),
-            }),
-            multisample: wgpu::MultisampleState {
-                count: sample_count,              // 2.
-                mask: !0,                         // 3.
-                alpha_to_coverage_enabled: false, // 4.
-            },
-            multiview: None, // 5.
-            cache: None,     // 6.
-        });
-        Ok(Self {
-            pipeline: render_pipeline,
-        })
+//
+// Profiling
+impl Pipeline {
+    // Frame stats in the corner of the presented frame.
+    pub fn stats_overlay_render_pipeline(
+        config: &wgpu::SurfaceConfiguration,
+        stats_text_bind_group_layout: &wgpu::BindGroupLayout,
+    ) -> PipelineDesc {
+        PipelineDesc::new(
+            "Stats Overlay Render Pipeline",
+            shader!("Stats Overlay Shader", "stats_overlay.wgsl"),
+        )
+        .bind_group_layouts(&[stats_text_bind_group_layout])
+        .target(config.format, Some(wgpu::BlendState::ALPHA_BLENDING))
+    }
+}
+
+//
+// Reduced-resolution reflections
+impl Pipeline {
+    // Fullscreen pass from the reduced reflection target into the framebuffer, limited to
+    // the stencil reference of one mirror. Depth is left to the mirror depth pass.
+    pub fn reflection_upsample_render_pipeline(
+        config: &wgpu::SurfaceConfiguration,
+        texture_bind_group_layout: &wgpu::BindGroupLayout,
+        sample_count: u32,
+    ) -> PipelineDesc {
+        PipelineDesc::new(
+            "Reflection Upsample Render Pipeline",
+            shader!("Reflection Upsample Shader", "reflection_upsample.wgsl"),
+        )
+        .bind_group_layouts(&[texture_bind_group_layout])
+        .target(config.format, None)
+        .stencil(wgpu::CompareFunction::Equal, wgpu::StencilOperation::Keep)
+        .sample_count(sample_count)
+    }
+}
+
+// /
+// / S C E N E   P I P E L I N E S
+// /
+
+// The bind group layouts the scene pipelines are built against. Kept for the lifetime
+// of the state, so a rebuild asks the cache for the same descriptions.
+pub struct BindGroupLayouts {
+    pub texture: wgpu::BindGroupLayout,
+    pub camera: wgpu::BindGroupLayout,
+    pub spin: wgpu::BindGroupLayout,
+    pub surface: wgpu::BindGroupLayout,
+    pub mirror_material: wgpu::BindGroupLayout,
+    pub mirror_plane: wgpu::BindGroupLayout,
+    pub ssr: wgpu::BindGroupLayout,
+    pub environment: wgpu::BindGroupLayout,
+    pub water: wgpu::BindGroupLayout,
+    pub stats_text: wgpu::BindGroupLayout,
+}
+
+impl BindGroupLayouts {
+    pub fn new(
+        device: &wgpu::Device,
+        texture: &wgpu::BindGroupLayout,
+        camera: &wgpu::BindGroupLayout,
+        spin: &wgpu::BindGroupLayout,
+        sample_count: u32,
+    ) -> Self {
+        Self {
+            texture: texture.clone(),
+            camera: camera.clone(),
+            spin: spin.clone(),
+            surface: SurfaceUniform::bind_group_layout(device),
+            mirror_material: MirrorMaterialUniform::bind_group_layout(device),
+            mirror_plane: MirrorPlaneUniform::bind_group_layout(device),
+            ssr: ScreenSpaceReflections::bind_group_layout(device, sample_count),
+            environment: EnvironmentMap::bind_group_layout(device),
+            water: Water::bind_group_layout(device),
+            stats_text: StatsOverlay::bind_group_layout(device),
+        }
+    }
+}
+
+// Every pipeline a frame uses. Rebuilt as a whole when a setting or a shader changes;
+// whatever did not change comes straight from the cache.
+pub struct ScenePipelines {
+    pub render: wgpu::RenderPipeline,
+    pub stencil: wgpu::RenderPipeline,
+    pub reflection: wgpu::RenderPipeline,
+    pub mirror_surface: wgpu::RenderPipeline,
+    pub mirror_coat: wgpu::RenderPipeline,
+    pub nested_stencil: wgpu::RenderPipeline,
+    pub clear_depth: wgpu::RenderPipeline,
+    pub nested_surface: wgpu::RenderPipeline,
+    pub nested_coat: wgpu::RenderPipeline,
+    pub mirror_depth: wgpu::RenderPipeline,
+    pub reflection_upsample: wgpu::RenderPipeline,
+    pub occlusion_probe: wgpu::RenderPipeline,
+    pub reflection_texture: wgpu::RenderPipeline,
+    pub mirror_surface_textured: wgpu::RenderPipeline,
+    pub ssr: wgpu::RenderPipeline,
+    pub environment_face: wgpu::RenderPipeline,
+    pub environment: wgpu::RenderPipeline,
+    pub water: wgpu::RenderPipeline,
+    pub debug_stencil: wgpu::RenderPipeline, // DEBUG
+    pub shader_error: wgpu::RenderPipeline,
+    pub stats_overlay: wgpu::RenderPipeline,
+}
+
+impl ScenePipelines {
+    pub fn new(
+        cache: &mut PipelineCache,
+        device: &wgpu::Device,
+        config: &wgpu::SurfaceConfiguration,
+        layouts: &BindGroupLayouts,
+        sample_count: u32,
+        wireframe: bool,
+    ) -> Self {
+        let mut get = |desc: PipelineDesc| cache.get(device, &desc);
+        // The reflected cameras live in each mirror and share the camera layout.
+        let camera_reflected = &layouts.camera;
+
+        Self {
+            render: get(Pipeline::build_render_pipeline(
+                config,
+                sample_count,
+                &layouts.texture,
+                &layouts.camera,
+                &layouts.spin,
+                &layouts.surface,
+            )
+            .wireframe(wireframe)),
+            stencil: get(Pipeline::mask_render_pipeline(
+                &layouts.camera,
+                sample_count,
+            )),
+            reflection: get(Pipeline::reflection_render_pipeline(
+                config,
+                &layouts.texture,
+                camera_reflected,
+                &layouts.spin,
+                &layouts.mirror_plane,
+                sample_count,
+            )),
+            mirror_surface: get(Pipeline::mirror_surface_render_pipeline(
+                config,
+                &layouts.camera,
+                &layouts.mirror_material,
+                &layouts.mirror_plane,
+                SurfaceLayer::Reflection,
+                sample_count,
+            )),
+            mirror_coat: get(Pipeline::mirror_surface_render_pipeline(
+                config,
+                &layouts.camera,
+                &layouts.mirror_material,
+                &layouts.mirror_plane,
+                SurfaceLayer::Coat,
+                sample_count,
+            )),
+            // Nested mirror pipelines (mirror seen inside a mirror)
+            nested_stencil: get(Pipeline::nested_mask_render_pipeline(
+                config,
+                &layouts.camera,
+                sample_count,
+            )),
+            clear_depth: get(Pipeline::clear_depth_render_pipeline(config, sample_count)),
+            nested_surface: get(Pipeline::nested_surface_render_pipeline(
+                config,
+                &layouts.camera,
+                &layouts.mirror_material,
+                &layouts.mirror_plane,
+                SurfaceLayer::Reflection,
+                sample_count,
+            )),
+            nested_coat: get(Pipeline::nested_surface_render_pipeline(
+                config,
+                &layouts.camera,
+                &layouts.mirror_material,
+                &layouts.mirror_plane,
+                SurfaceLayer::Coat,
+                sample_count,
+            )),
+            mirror_depth: get(Pipeline::mirror_depth_render_pipeline(
+                config,
+                &layouts.camera,
+                sample_count,
+            )),
+            // Reduced-resolution reflections
+            reflection_upsample: get(Pipeline::reflection_upsample_render_pipeline(
+                config,
+                &layouts.texture,
+                sample_count,
+            )),
+            // Occlusion culling of mirrors
+            occlusion_probe: get(Pipeline::occlusion_probe_render_pipeline(
+                config,
+                &layouts.camera,
+                sample_count,
+            )),
+            // Render-to-texture mirrors
+            reflection_texture: get(Pipeline::reflection_texture_render_pipeline(
+                config,
+                &layouts.texture,
+                camera_reflected,
+                &layouts.spin,
+                &layouts.mirror_plane,
+            )),
+            mirror_surface_textured: get(Pipeline::mirror_surface_textured_render_pipeline(
+                config,
+                &layouts.texture,
+                &layouts.camera,
+                &layouts.mirror_material,
+                &layouts.mirror_plane,
+                sample_count,
+            )),
+            ssr: get(Pipeline::ssr_render_pipeline(
+                config,
+                &layouts.ssr,
+                sample_count,
+            )),
+            // Environment maps
+            environment_face: get(Pipeline::environment_face_render_pipeline(
+                config,
+                &layouts.texture,
+                &layouts.camera,
+                &layouts.spin,
+            )),
+            environment: get(Pipeline::environment_render_pipeline(
+                config,
+                &layouts.environment,
+                &layouts.camera,
+                sample_count,
+            )),
+            water: get(Pipeline::water_render_pipeline(
+                config,
+                &layouts.water,
+                &layouts.camera,
+                sample_count,
+            )),
+            debug_stencil: get(DebugPipeline::debug_render_pipeline(config, sample_count)),
+            shader_error: get(Pipeline::shader_error_render_pipeline(config)),
+            stats_overlay: get(Pipeline::stats_overlay_render_pipeline(
+                config,
+                &layouts.stats_text,
+            )),
+        }
     }
 }
@@ -0,0 +1,392 @@
+use std::{
+    borrow::Cow,
+    collections::{HashMap, HashSet},
+};
+
+use crate::wgsl::{self, Preprocessor};
+
+// A render pipeline as plain data. Two equal descriptions build the same pipeline, so a
+// description is also its key in the `PipelineCache`: a variant (wireframe, another
+// sample count) is a copy with one field changed.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct PipelineDesc {
+    pub label: &'static str,
+    pub shader: Shader,
+    pub vertex_entry: &'static str,
+    // None: depth/stencil only pipeline
+    pub fragment_entry: Option<&'static str>,
+    pub vertex_buffers: Vec<wgpu::VertexBufferLayout<'static>>,
+    pub bind_group_layouts: Vec<wgpu::BindGroupLayout>,
+    pub targets: Vec<Option<wgpu::ColorTargetState>>,
+    pub primitive: wgpu::PrimitiveState,
+    pub depth_stencil: Option<wgpu::DepthStencilState>,
+    pub multisample: wgpu::MultisampleState,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Shader {
+    pub label: &'static str,
+    // Under src/shaders, where hot reload reads it again
+    pub file: &'static str,
+    // As compiled in, before preprocessing; the cache may have a newer one for `file`
+    pub source: &'static str,
+    // `#define`d before the first line of `file`
+    pub defines: Vec<&'static str>,
+}
+
+// `shader!("label", "file.wgsl")`: a shader of src/shaders, compiled in.
+macro_rules! shader {
+    ($label:expr, $file:literal) => {
+        $crate::pipeline_desc::Shader::new($label, $file, include_str!(concat!("shaders/", $file)))
+    };
+}
+pub(crate) use shader;
+
+impl Shader {
+    pub fn new(label: &'static str, file: &'static str, source: &'static str) -> Self {
+        Self {
+            label,
+            file,
+            source,
+            defines: Vec::new(),
+        }
+    }
+
+    pub fn define(mut self, name: &'static str) -> Self {
+        self.defines.push(name);
+        self
+    }
+
+    // `text` (the file's) with its imports and #ifdefs resolved, imports found by `load`
+    pub fn preprocess(
+        &self,
+        text: &str,
+        load: &dyn Fn(&str) -> Option<Cow<'static, str>>,
+    ) -> anyhow::Result<String> {
+        Preprocessor::new(&self.defines, load).run(self.file, text)
+    }
+}
+
+impl PipelineDesc {
+    // `vs_main` and `fs_main`, no buffers, no targets, no depth, one sample.
+    pub fn new(label: &'static str, shader: Shader) -> Self {
+        Self {
+            label,
+            shader,
+            vertex_entry: "vs_main",
+            fragment_entry: Some("fs_main"),
+            vertex_buffers: Vec::new(),
+            bind_group_layouts: Vec::new(),
+            targets: Vec::new(),
+            primitive: wgpu::PrimitiveState::default(),
+            depth_stencil: None,
+            multisample: wgpu::MultisampleState::default(),
+        }
+    }
+
+    pub fn fragment(mut self, entry_point: Option<&'static str>) -> Self {
+        self.fragment_entry = entry_point;
+        self
+    }
+
+    pub fn vertex_buffers(mut self, layouts: &[wgpu::VertexBufferLayout<'static>]) -> Self {
+        self.vertex_buffers = layouts.to_vec();
+        self
+    }
+
+    pub fn bind_group_layouts(mut self, layouts: &[&wgpu::BindGroupLayout]) -> Self {
+        self.bind_group_layouts = layouts.iter().map(|&layout| layout.clone()).collect();
+        self
+    }
+
+    pub fn target(mut self, format: wgpu::TextureFormat, blend: Option<wgpu::BlendState>) -> Self {
+        self.targets.push(Some(wgpu::ColorTargetState {
+            format,
+            blend,
+            write_mask: wgpu::ColorWrites::ALL,
+        }));
+        self
+    }
+
+    // A color target the pipeline never writes, to share a pass with ones that do.
+    pub fn masked_target(mut self, format: wgpu::TextureFormat) -> Self {
+        self.targets.push(Some(wgpu::ColorTargetState {
+            format,
+            blend: None,
+            write_mask: wgpu::ColorWrites::empty(),
+        }));
+        self
+    }
+
+    pub fn depth(mut self, write: bool, compare: wgpu::CompareFunction) -> Self {
+        let depth_stencil = self.depth_stencil_mut();
+        depth_stencil.depth_write_enabled = write;
+        depth_stencil.depth_compare = compare;
+        self
+    }
+
+    // Front faces only, back faces always pass and keep. The stencil is written only
+    // when `pass_op` changes it.
+    pub fn stencil(self, compare: wgpu::CompareFunction, pass_op: wgpu::StencilOperation) -> Self {
+        self.stencil_faces(compare, pass_op, false)
+    }
+
+    pub fn two_sided_stencil(
+        self,
+        compare: wgpu::CompareFunction,
+        pass_op: wgpu::StencilOperation,
+    ) -> Self {
+        self.stencil_faces(compare, pass_op, true)
+    }
+
+    fn stencil_faces(
+        mut self,
+        compare: wgpu::CompareFunction,
+        pass_op: wgpu::StencilOperation,
+        two_sided: bool,
+    ) -> Self {
+        let face = wgpu::StencilFaceState {
+            compare,
+            fail_op: wgpu::StencilOperation::Keep,
+            depth_fail_op: wgpu::StencilOperation::Keep,
+            pass_op,
+        };
+        self.depth_stencil_mut().stencil = wgpu::StencilState {
+            front: face,
+            back: if two_sided {
+                face
+            } else {
+                wgpu::StencilFaceState::IGNORE
+            },
+            read_mask: 0xFF,
+            write_mask: if pass_op == wgpu::StencilOperation::Keep {
+                0x00
+            } else {
+                0xFF
+            },
+        };
+        self
+    }
+
+    pub fn cull(mut self, cull_mode: Option<wgpu::Face>) -> Self {
+        self.primitive.cull_mode = cull_mode;
+        self
+    }
+
+    pub fn sample_count(mut self, count: u32) -> Self {
+        self.multisample.count = count;
+        self
+    }
+
+    // Requires Features::POLYGON_MODE_LINE
+    pub fn wireframe(mut self, wireframe: bool) -> Self {
+        self.primitive.polygon_mode = if wireframe {
+            wgpu::PolygonMode::Line
+        } else {
+            wgpu::PolygonMode::Fill
+        };
+        self
+    }
+
+    // Depth/stencil state, with depth always passing until `depth` says otherwise.
+    fn depth_stencil_mut(&mut self) -> &mut wgpu::DepthStencilState {
+        self.depth_stencil
+            .get_or_insert_with(|| wgpu::DepthStencilState {
+                format: wgpu::TextureFormat::Depth24PlusStencil8,
+                depth_write_enabled: false,
+                depth_compare: wgpu::CompareFunction::Always,
+                stencil: wgpu::StencilState::default(),
+                bias: wgpu::DepthBiasState::default(),
+            })
+    }
+
+    pub fn build(
+        &self,
+        device: &wgpu::Device,
+        shader: &wgpu::ShaderModule,
+    ) -> wgpu::RenderPipeline {
+        let bind_group_layouts = self.bind_group_layouts.iter().collect::<Vec<_>>();
+        let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some(self.label),
+            bind_group_layouts: &bind_group_layouts,
+            push_constant_ranges: &[],
+        });
+
+        device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some(self.label),
+            layout: Some(&layout),
+            vertex: wgpu::VertexState {
+                module: shader,
+                entry_point: Some(self.vertex_entry),
+                buffers: &self.vertex_buffers,
+                compilation_options: wgpu::PipelineCompilationOptions::default(),
+            },
+            fragment: self.fragment_entry.map(|entry_point| wgpu::FragmentState {
+                module: shader,
+                entry_point: Some(entry_point),
+                targets: &self.targets,
+                compilation_options: wgpu::PipelineCompilationOptions::default(),
+            }),
+            primitive: self.primitive,
+            depth_stencil: self.depth_stencil.clone(),
+            multisample: self.multisample,
+            multiview: None,
+            cache: None,
+        })
+    }
+}
+
+// Pipelines by description and shader modules by source: asking twice for the same
+// pipeline hands back the one built the first time.
+//
+// A description keeps the shader it was compiled with. A reloaded shader file, imported
+// ones included, replaces the pipelines whose preprocessed source it changes in place,
+// under the same descriptions.
+//
+// It only keeps what is still asked for: `sweep` drops the pipelines no description
+// asked for since the last sweep, and modules go with the last pipeline built from them.
+#[derive(Default)]
+pub struct PipelineCache {
+    modules: HashMap<String, wgpu::ShaderModule>,
+    // With the preprocessed source each was built from
+    pipelines: HashMap<PipelineDesc, (String, wgpu::RenderPipeline)>,
+    // Asked for since the last sweep
+    used: HashSet<PipelineDesc>,
+    // Reloaded text of a shader file, by its path under src/shaders
+    reloaded: HashMap<String, String>,
+}
+
+impl PipelineCache {
+    pub fn get(&mut self, device: &wgpu::Device, desc: &PipelineDesc) -> wgpu::RenderPipeline {
+        if !self.used.contains(desc) {
+            self.used.insert(desc.clone());
+        }
+        if let Some((_, pipeline)) = self.pipelines.get(desc) {
+            return pipeline.clone();
+        }
+
+        let source = self.source(&desc.shader).unwrap_or_else(|error| {
+            // A reloaded file this variant can't use: it gets the compiled-in shader
+            log::error!("{}: {error:#}", desc.shader.file);
+            desc.shader
+                .preprocess(desc.shader.source, &wgsl::bundled)
+                .expect("compiled-in shaders preprocess")
+        });
+        let shader = self
+            .modules
+            .entry(source.clone())
+            .or_insert_with_key(|source| create_shader_module(device, &desc.shader, source));
+        let pipeline = desc.build(device, shader);
+        self.pipelines
+            .insert(desc.clone(), (source, pipeline.clone()));
+        pipeline
+    }
+
+    // After a rebuild: what the previous settings used and the current ones don't (the
+    // other sample count, the wireframe variants) is dropped.
+    pub fn sweep(&mut self) {
+        self.pipelines.retain(|desc, _| self.used.contains(desc));
+        self.used.clear();
+        self.drop_unused_modules();
+    }
+
+    fn drop_unused_modules(&mut self) {
+        self.modules.retain(|source, _| {
+            self.pipelines
+                .values()
+                .any(|(built_from, _)| built_from == source)
+        });
+    }
+
+    // The shader's preprocessed source, reloaded files first
+    fn source(&self, shader: &Shader) -> anyhow::Result<String> {
+        let load = |path: &str| {
+            self.reloaded
+                .get(path)
+                .map(|text| Cow::Owned(text.clone()))
+                .or_else(|| wgsl::bundled(path))
+        };
+        let text = self
+            .reloaded
+            .get(shader.file)
+            .map_or(shader.source, String::as_str);
+        shader.preprocess(text, &load)
+    }
+
+    // Takes `text` as the new `file` (path under src/shaders) and rebuilds every pipeline
+    // whose preprocessed source changes. Nothing changes unless they all preprocess and
+    // validate: the last good pipelines stay in use. Returns how many changed.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn reload(
+        &mut self,
+        device: &wgpu::Device,
+        file: &str,
+        text: String,
+    ) -> anyhow::Result<usize> {
+        let previous = self.reloaded.insert(file.to_string(), text);
+        let rebuilt = self.rebuild_changed(device);
+        if rebuilt.is_err() {
+            match previous {
+                Some(text) => self.reloaded.insert(file.to_string(), text),
+                None => self.reloaded.remove(file),
+            };
+        }
+        rebuilt
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    fn rebuild_changed(&mut self, device: &wgpu::Device) -> anyhow::Result<usize> {
+        let mut stale = Vec::new();
+        for (desc, (built_from, _)) in &self.pipelines {
+            let source = self.source(&desc.shader)?;
+            if source != *built_from {
+                stale.push((desc.clone(), source));
+            }
+        }
+        if stale.is_empty() {
+            return Ok(0);
+        }
+
+        device.push_error_scope(wgpu::ErrorFilter::Validation);
+        let mut modules = HashMap::new();
+        let rebuilt = stale
+            .into_iter()
+            .map(|(desc, source)| {
+                let shader = modules.entry(source.clone()).or_insert_with_key(|source| {
+                    create_shader_module(device, &desc.shader, source)
+                });
+                let pipeline = desc.build(device, shader);
+                (desc, (source, pipeline))
+            })
+            .collect::<Vec<_>>();
+        if let Some(error) = pollster::block_on(device.pop_error_scope()) {
+            anyhow::bail!("{error}");
+        }
+
+        let count = rebuilt.len();
+        self.modules.extend(modules);
+        self.pipelines.extend(rebuilt);
+        // The sources they replaced
+        self.drop_unused_modules();
+        Ok(count)
+    }
+
+    pub fn len(&self) -> usize {
+        self.pipelines.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pipelines.is_empty()
+    }
+}
+
+fn create_shader_module(
+    device: &wgpu::Device,
+    shader: &Shader,
+    source: &str,
+) -> wgpu::ShaderModule {
+    device.create_shader_module(wgpu::ShaderModuleDescriptor {
+        label: Some(shader.label),
+        source: wgpu::ShaderSource::Wgsl(source.into()),
+    })
+}
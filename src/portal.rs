@@ -0,0 +1,43 @@
+use std::rc::Rc;
+
+use cgmath::{Matrix4, SquareMatrix};
+
+use crate::{
+    mirror::{MirrorDesc, MirrorMaterial, MirrorShape},
+    vertex::Instance,
+};
+
+// A doorway between two places: looking into the front of the entry shows what lies in
+// front of the exit. Drawn like a mirror (stencil mask, virtual camera, clipping, nested
+// recursion), only the virtual camera is moved by the entry -> exit transform instead of
+// being reflected.
+pub struct Portal {
+    pub entry: Instance,
+    pub exit: Instance,
+}
+
+impl Portal {
+    pub fn new(entry: Instance, exit: Instance) -> Self {
+        Self { entry, exit }
+    }
+
+    // One-way portal with a clear surface, ready for `Mirror::build_mirrors`.
+    pub fn into_desc(self, shape: Rc<MirrorShape>, scale: f32) -> MirrorDesc {
+        MirrorDesc {
+            shape,
+            instance: self.entry,
+            scale,
+            material: MirrorMaterial::clear(),
+            exit: Some(self.exit),
+        }
+    }
+}
+
+// Takes the world seen through the exit to where it shows up behind the entry, i.e. the
+// matrix a virtual camera is built with (view * transform), like a reflection matrix.
+// Both planes are mesh-to-world transforms with their origin on the portal plane; the
+// half turn around the mesh y axis makes the entry's front look out of the exit's front.
+pub fn portal_view_transform(entry_plane: Matrix4<f32>, exit_plane: Matrix4<f32>) -> Matrix4<f32> {
+    let half_turn = Matrix4::from_angle_y(cgmath::Deg(180.0));
+    entry_plane * half_turn * exit_plane.invert().unwrap_or(Matrix4::identity())
+}
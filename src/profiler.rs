@@ -0,0 +1,283 @@
+use std::{
+    collections::VecDeque,
+    sync::{Arc, Mutex},
+};
+
+use instant::{Duration, Instant};
+
+// / P R O F I L E R
+// / GPU time of every render pass from timestamp queries (where the adapter has
+// / TIMESTAMP_QUERY), read back a frame or two later like the occlusion queries, and the
+// / CPU time spent encoding the frame. Averaged over the last frames measured.
+
+// Passes timed per frame; any after them go untimed
+pub const MAX_TIMED_PASSES: u32 = 128;
+// Frames averaged over
+const WINDOW: usize = 60;
+const LOG_INTERVAL: Duration = Duration::from_secs(2);
+
+#[derive(Default)]
+pub struct RollingAverage {
+    samples: VecDeque<f32>,
+    sum: f32,
+}
+
+impl RollingAverage {
+    pub fn push(&mut self, sample: f32) {
+        if self.samples.len() == WINDOW {
+            self.sum -= self.samples.pop_front().unwrap_or_default();
+        }
+        self.samples.push_back(sample);
+        self.sum += sample;
+    }
+
+    pub fn average(&self) -> f32 {
+        if self.samples.is_empty() {
+            0.0
+        } else {
+            self.sum / self.samples.len() as f32
+        }
+    }
+}
+
+struct Timestamps {
+    query_set: wgpu::QuerySet,
+    resolve_buffer: wgpu::Buffer,
+    readback_buffer: wgpu::Buffer,
+    // Nanoseconds per tick
+    period: f32,
+    // Names of the passes timed in the batch being read back, in recording order
+    in_flight: Option<Vec<&'static str>>,
+    map_requested: bool,
+    // Outcome of the pending map_async: Some(true) once the results can be read
+    mapped: Arc<Mutex<Option<bool>>>,
+}
+
+impl Timestamps {
+    fn new(device: &wgpu::Device, queue: &wgpu::Queue) -> Self {
+        let count = 2 * MAX_TIMED_PASSES;
+        let size = (count as usize * std::mem::size_of::<u64>()) as wgpu::BufferAddress;
+        Self {
+            query_set: device.create_query_set(&wgpu::QuerySetDescriptor {
+                label: Some("pass timestamp queries"),
+                ty: wgpu::QueryType::Timestamp,
+                count,
+            }),
+            resolve_buffer: device.create_buffer(&wgpu::BufferDescriptor {
+                label: Some("Timestamp Resolve Buffer"),
+                size,
+                usage: wgpu::BufferUsages::QUERY_RESOLVE | wgpu::BufferUsages::COPY_SRC,
+                mapped_at_creation: false,
+            }),
+            readback_buffer: device.create_buffer(&wgpu::BufferDescriptor {
+                label: Some("Timestamp Readback Buffer"),
+                size,
+                usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
+                mapped_at_creation: false,
+            }),
+            period: queue.get_timestamp_period(),
+            in_flight: None,
+            map_requested: false,
+            mapped: Arc::new(Mutex::new(None)),
+        }
+    }
+}
+
+pub struct Profiler {
+    enabled: bool,
+    // None without TIMESTAMP_QUERY: CPU times only
+    timestamps: Option<Timestamps>,
+    // GPU milliseconds per pass name (passes sharing a name add up), in recording order
+    passes: Vec<(&'static str, RollingAverage)>,
+    gpu_frame: RollingAverage,
+    cpu_encode: RollingAverage,
+    last_log: Instant,
+}
+
+impl Profiler {
+    pub fn new(device: &wgpu::Device, queue: &wgpu::Queue) -> Self {
+        let timestamps = device
+            .features()
+            .contains(wgpu::Features::TIMESTAMP_QUERY)
+            .then(|| Timestamps::new(device, queue));
+        if timestamps.is_none() {
+            log::info!("profiler: no TIMESTAMP_QUERY, CPU times only");
+        }
+        Self {
+            enabled: false,
+            timestamps,
+            passes: Vec::new(),
+            gpu_frame: RollingAverage::default(),
+            cpu_encode: RollingAverage::default(),
+            last_log: Instant::now(),
+        }
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.enabled
+    }
+
+    pub fn set_enabled(&mut self, enabled: bool) {
+        self.enabled = enabled;
+        // A fresh window: averages from before would mix in other settings
+        self.passes.clear();
+        self.gpu_frame = RollingAverage::default();
+        self.cpu_encode = RollingAverage::default();
+        self.last_log = Instant::now();
+    }
+
+    // Collect the last batch of timestamps if it arrived. True when this frame's passes
+    // can be timed (with `query_set`), i.e. the readback buffer is free again.
+    pub fn begin_frame(&mut self, device: &wgpu::Device) -> bool {
+        if !self.enabled {
+            return false;
+        }
+        if self.last_log.elapsed() >= LOG_INTERVAL {
+            self.last_log = Instant::now();
+            let stats = self
+                .rows()
+                .iter()
+                .map(|(name, ms)| format!("{name} {ms:.3} ms"))
+                .collect::<Vec<_>>();
+            log::info!("frame stats: {}", stats.join(", "));
+        }
+
+        let Some(timestamps) = &mut self.timestamps else {
+            return false;
+        };
+        if timestamps.in_flight.is_some() {
+            let _ = device.poll(wgpu::PollType::Poll);
+        }
+        let mapped = timestamps.mapped.lock().unwrap().take();
+        match (mapped, timestamps.in_flight.take()) {
+            (Some(true), Some(timed)) => {
+                let data = timestamps.readback_buffer.slice(..).get_mapped_range();
+                let ticks: &[u64] = bytemuck::cast_slice(&data);
+                let period = timestamps.period;
+                let ms = |begin: u64, end: u64| end.saturating_sub(begin) as f32 * period / 1e6;
+
+                let mut frame: Vec<(&'static str, f32)> = Vec::new();
+                for (name, pair) in timed.iter().zip(ticks.chunks_exact(2)) {
+                    match frame.iter_mut().find(|(timed, _)| timed == name) {
+                        Some((_, total)) => *total += ms(pair[0], pair[1]),
+                        None => frame.push((name, ms(pair[0], pair[1]))),
+                    }
+                }
+                let used = &ticks[..2 * timed.len()];
+                if let (Some(&begin), Some(&end)) = (used.iter().min(), used.iter().max()) {
+                    self.gpu_frame.push(ms(begin, end));
+                }
+                drop(data);
+                timestamps.readback_buffer.unmap();
+
+                // Passes that didn't run this frame (a setting changed) are dropped
+                let mut passes = std::mem::take(&mut self.passes);
+                self.passes = frame
+                    .into_iter()
+                    .map(|(name, time)| {
+                        let mut average = passes
+                            .iter()
+                            .position(|(timed, _)| *timed == name)
+                            .map(|i| passes.swap_remove(i).1)
+                            .unwrap_or_default();
+                        average.push(time);
+                        (name, average)
+                    })
+                    .collect();
+            }
+            (Some(false), _) => {}
+            (_, in_flight) => timestamps.in_flight = in_flight,
+        }
+
+        timestamps.in_flight.is_none()
+    }
+
+    pub fn query_set(&self) -> Option<&wgpu::QuerySet> {
+        self.timestamps
+            .as_ref()
+            .map(|timestamps| &timestamps.query_set)
+    }
+
+    // `passes`: the graph's passes in recording order, timed with the query set from
+    // `begin_frame`.
+    pub fn resolve(&mut self, encoder: &mut wgpu::CommandEncoder, mut passes: Vec<&'static str>) {
+        let Some(timestamps) = &mut self.timestamps else {
+            return;
+        };
+        passes.truncate(MAX_TIMED_PASSES as usize);
+        if passes.is_empty() {
+            return;
+        }
+        let count = 2 * passes.len() as u32;
+        encoder.resolve_query_set(
+            &timestamps.query_set,
+            0..count,
+            &timestamps.resolve_buffer,
+            0,
+        );
+        encoder.copy_buffer_to_buffer(
+            &timestamps.resolve_buffer,
+            0,
+            &timestamps.readback_buffer,
+            0,
+            count as u64 * std::mem::size_of::<u64>() as u64,
+        );
+        timestamps.in_flight = Some(passes);
+        timestamps.map_requested = false;
+    }
+
+    // Once the resolve is submitted.
+    pub fn map(&mut self) {
+        let Some(timestamps) = &mut self.timestamps else {
+            return;
+        };
+        if timestamps.in_flight.is_none() || timestamps.map_requested {
+            return;
+        }
+        timestamps.map_requested = true;
+        let mapped = timestamps.mapped.clone();
+        timestamps
+            .readback_buffer
+            .slice(..)
+            .map_async(wgpu::MapMode::Read, move |result| {
+                *mapped.lock().unwrap() = Some(result.is_ok());
+            });
+    }
+
+    // From the command encoder's creation to `finish`.
+    pub fn record_cpu_encode(&mut self, elapsed: Duration) {
+        if self.enabled {
+            self.cpu_encode.push(elapsed.as_secs_f32() * 1e3);
+        }
+    }
+
+    // Averages in milliseconds: CPU encode, then GPU frame and passes.
+    fn rows(&self) -> Vec<(&'static str, f32)> {
+        let mut rows = vec![("cpu encode", self.cpu_encode.average())];
+        if self.timestamps.is_some() {
+            rows.push(("gpu frame", self.gpu_frame.average()));
+            rows.extend(
+                self.passes
+                    .iter()
+                    .map(|(name, average)| (*name, average.average())),
+            );
+        }
+        rows
+    }
+
+    // The readout: a title, then one "name  time" line per measurement.
+    pub fn lines(&self) -> Vec<String> {
+        let rows = self.rows();
+        let width = rows.iter().map(|(name, _)| name.len()).max().unwrap_or(0);
+
+        let mut lines = vec![format!("frame stats, last {WINDOW} frames")];
+        lines.extend(
+            rows.iter()
+                .map(|(name, ms)| format!("{name:<width$} {ms:>7.3} ms")),
+        );
+        if self.timestamps.is_none() {
+            lines.push("no gpu timestamps on this adapter".to_string());
+        }
+        lines
+    }
+}
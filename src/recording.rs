@@ -0,0 +1,145 @@
+// / R E C O R D I N G
+// / Consecutive frames written to an animated GIF or numbered PNGs. While recording, the
+// / scene advances by a fixed step per frame instead of the real time between frames, so the
+// / animation comes out smooth however long a frame takes to read back and encode.
+
+use std::{fs::File, io::BufWriter, path::PathBuf};
+
+use image::codecs::gif::{GifEncoder, Repeat};
+
+use crate::offscreen::Readback;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RecordingFormat {
+    // One looping .gif file
+    Gif,
+    // frame-00000.png, frame-00001.png, ... in a directory
+    PngSequence,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum RecordingLength {
+    Frames(u32),
+    // In simulated time: seconds * fps frames
+    Seconds(f32),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct RecordingDesc {
+    pub format: RecordingFormat,
+    pub length: RecordingLength,
+    // Frames per simulated second. GIF delays are in hundredths of a second, so 20, 25 or
+    // 50 play back at exactly this rate.
+    pub fps: u32,
+    // The .gif file, or the directory of the PNGs (created if missing)
+    pub path: PathBuf,
+}
+
+impl RecordingDesc {
+    // A GIF of `seconds` at 25 fps, named after the current time.
+    pub fn gif(seconds: f32) -> Self {
+        Self {
+            format: RecordingFormat::Gif,
+            length: RecordingLength::Seconds(seconds),
+            fps: 25,
+            path: format!("recording-{}.gif", crate::screenshot::timestamp()).into(),
+        }
+    }
+
+    pub fn frame_count(&self) -> u32 {
+        match self.length {
+            RecordingLength::Frames(frames) => frames,
+            RecordingLength::Seconds(seconds) => (seconds * self.fps as f32).ceil() as u32,
+        }
+    }
+}
+
+enum Sink {
+    Gif(Box<GifEncoder<BufWriter<File>>>),
+    Png(PathBuf),
+}
+
+pub struct Recorder {
+    desc: RecordingDesc,
+    sink: Sink,
+    written: u32,
+    // The frame copied this frame, read once it is submitted
+    readback: Option<Readback>,
+}
+
+impl Recorder {
+    pub fn new(desc: RecordingDesc) -> anyhow::Result<Self> {
+        anyhow::ensure!(desc.fps > 0, "recording: fps must be at least 1");
+        let sink = match desc.format {
+            RecordingFormat::Gif => {
+                let file = BufWriter::new(File::create(&desc.path)?);
+                // Speed 10 of 1..=30: a little color quality for much faster quantization
+                let mut encoder = GifEncoder::new_with_speed(file, 10);
+                encoder.set_repeat(Repeat::Infinite)?;
+                Sink::Gif(Box::new(encoder))
+            }
+            RecordingFormat::PngSequence => {
+                std::fs::create_dir_all(&desc.path)?;
+                Sink::Png(desc.path.clone())
+            }
+        };
+        log::info!(
+            "recording {} frames at {} fps to {}",
+            desc.frame_count(),
+            desc.fps,
+            desc.path.display()
+        );
+        Ok(Self {
+            desc,
+            sink,
+            written: 0,
+            readback: None,
+        })
+    }
+
+    // What `State::update` advances the scene by, in seconds.
+    pub fn time_step(&self) -> f32 {
+        1.0 / self.desc.fps as f32
+    }
+
+    pub fn is_done(&self) -> bool {
+        self.written >= self.desc.frame_count()
+    }
+
+    // Copies `texture`, the finished frame.
+    pub fn record(
+        &mut self,
+        device: &wgpu::Device,
+        encoder: &mut wgpu::CommandEncoder,
+        texture: &wgpu::Texture,
+    ) -> anyhow::Result<()> {
+        self.readback = Some(Readback::new(device, encoder, texture)?);
+        Ok(())
+    }
+
+    // Once the frame is submitted: waits for the copy and writes it.
+    pub fn finish(&mut self, device: &wgpu::Device) -> anyhow::Result<()> {
+        let Some(readback) = self.readback.take() else {
+            return Ok(());
+        };
+        let frame = pollster::block_on(readback.read(device))?;
+        match &mut self.sink {
+            Sink::Gif(encoder) => {
+                let delay = image::Delay::from_numer_denom_ms(1000, self.desc.fps);
+                encoder.encode_frame(image::Frame::from_parts(frame, 0, 0, delay))?;
+            }
+            Sink::Png(dir) => frame.save(dir.join(format!("frame-{:05}.png", self.written)))?,
+        }
+        self.written += 1;
+        Ok(())
+    }
+
+    // The GIF trailer is written when the encoder is dropped.
+    pub fn stop(self) {
+        log::info!(
+            "recording: {} frames written to {}",
+            self.written,
+            self.desc.path.display()
+        );
+    }
+}
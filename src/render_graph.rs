@@ -0,0 +1,381 @@
+use std::{cell::RefCell, collections::HashMap};
+
+use anyhow::bail;
+
+// A frame as a list of render passes. Each pass declares its attachments with their
+// load/store ops and the passes it must come after; the graph orders them, creates the
+// transient targets they ask for and records them into one encoder.
+//
+// Dependencies are by name: several passes may share one (e.g. one pass per mirror), and
+// waiting on a name waits on all of them. A name no pass has this frame is already done,
+// but every name must be one of those the graph was created with.
+pub struct RenderGraph<'a> {
+    // Every name a pass of this graph may have, whether or not it is added this frame
+    pass_names: &'a [&'static str],
+    passes: Vec<GraphPass<'a>>,
+    // Begin/end timestamps of the first passes recorded, two queries per pass
+    timestamps: Option<(&'a wgpu::QuerySet, u32)>,
+}
+
+type Record<'a> = Box<dyn FnOnce(&mut wgpu::RenderPass<'a>) + 'a>;
+
+pub struct GraphPass<'a> {
+    name: &'static str,
+    color_attachments: Vec<Option<ColorAttachment<'a>>>,
+    depth_stencil_attachment: Option<wgpu::RenderPassDepthStencilAttachment<'a>>,
+    occlusion_query_set: Option<&'a wgpu::QuerySet>,
+    after: Vec<&'static str>,
+    record: Record<'a>,
+}
+
+impl<'a> GraphPass<'a> {
+    // `record` draws into the pass once the graph has begun it.
+    pub fn new(name: &'static str, record: impl FnOnce(&mut wgpu::RenderPass<'a>) + 'a) -> Self {
+        Self {
+            name,
+            color_attachments: Vec::new(),
+            depth_stencil_attachment: None,
+            occlusion_query_set: None,
+            after: Vec::new(),
+            record: Box::new(record),
+        }
+    }
+
+    pub fn color(mut self, attachment: impl Into<ColorAttachment<'a>>) -> Self {
+        self.color_attachments.push(Some(attachment.into()));
+        self
+    }
+
+    pub fn depth_stencil(mut self, attachment: wgpu::RenderPassDepthStencilAttachment<'a>) -> Self {
+        self.depth_stencil_attachment = Some(attachment);
+        self
+    }
+
+    pub fn occlusion_queries(mut self, query_set: Option<&'a wgpu::QuerySet>) -> Self {
+        self.occlusion_query_set = query_set;
+        self
+    }
+
+    pub fn after(mut self, names: &[&'static str]) -> Self {
+        self.after.extend_from_slice(names);
+        self
+    }
+}
+
+// Where a color attachment renders: a view of ours, or a target the graph creates.
+pub enum Target<'a> {
+    View(&'a wgpu::TextureView),
+    Transient(TransientDesc),
+}
+
+pub struct ColorAttachment<'a> {
+    pub target: Target<'a>,
+    pub resolve_target: Option<&'a wgpu::TextureView>,
+    pub ops: wgpu::Operations<wgpu::Color>,
+}
+
+impl<'a> ColorAttachment<'a> {
+    pub fn new(view: &'a wgpu::TextureView, load: wgpu::LoadOp<wgpu::Color>) -> Self {
+        Self {
+            target: Target::View(view),
+            resolve_target: None,
+            ops: wgpu::Operations {
+                load,
+                store: wgpu::StoreOp::Store,
+            },
+        }
+    }
+
+    // Into `view`, through the multisampled target `msaa` when there is one.
+    pub fn resolved(
+        view: &'a wgpu::TextureView,
+        msaa: Option<TransientDesc>,
+        load: wgpu::LoadOp<wgpu::Color>,
+    ) -> Self {
+        match msaa {
+            Some(msaa) => Self {
+                target: Target::Transient(msaa),
+                resolve_target: Some(view),
+                ..Self::new(view, load)
+            },
+            None => Self::new(view, load),
+        }
+    }
+}
+
+impl<'a> From<wgpu::RenderPassColorAttachment<'a>> for ColorAttachment<'a> {
+    fn from(attachment: wgpu::RenderPassColorAttachment<'a>) -> Self {
+        Self {
+            target: Target::View(attachment.view),
+            resolve_target: attachment.resolve_target,
+            ops: attachment.ops,
+        }
+    }
+}
+
+// A render target only passes ever see, like the multisampled framebuffer.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct TransientDesc {
+    pub label: &'static str,
+    pub width: u32,
+    pub height: u32,
+    pub format: wgpu::TextureFormat,
+    pub sample_count: u32,
+}
+
+impl TransientDesc {
+    // Multisampled stand-in for a `config`-sized target, None without MSAA.
+    pub fn multisampled(
+        label: &'static str,
+        config: &wgpu::SurfaceConfiguration,
+        sample_count: u32,
+    ) -> Option<Self> {
+        (sample_count > 1).then_some(Self {
+            label,
+            width: config.width.max(1),
+            height: config.height.max(1),
+            format: config.format,
+            sample_count,
+        })
+    }
+}
+
+// Transient targets, kept from frame to frame. The same description gives the same
+// texture, so passes of one frame share it; the ones a frame didn't use are dropped.
+#[derive(Default)]
+pub struct TransientTargets {
+    views: RefCell<HashMap<TransientDesc, (wgpu::TextureView, bool)>>,
+}
+
+impl TransientTargets {
+    fn view(&self, device: &wgpu::Device, desc: TransientDesc) -> wgpu::TextureView {
+        let mut views = self.views.borrow_mut();
+        let (view, used) = views.entry(desc).or_insert_with(|| {
+            let texture = device.create_texture(&wgpu::TextureDescriptor {
+                label: Some(desc.label),
+                size: wgpu::Extent3d {
+                    width: desc.width,
+                    height: desc.height,
+                    depth_or_array_layers: 1,
+                },
+                mip_level_count: 1,
+                sample_count: desc.sample_count,
+                dimension: wgpu::TextureDimension::D2,
+                format: desc.format,
+                usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
+                view_formats: &[],
+            });
+            (texture.create_view(&Default::default()), false)
+        });
+        *used = true;
+        view.clone()
+    }
+
+    fn end_frame(&self) {
+        let mut views = self.views.borrow_mut();
+        views.retain(|_, (_, used)| std::mem::take(used));
+    }
+}
+
+impl<'a> RenderGraph<'a> {
+    pub fn new(pass_names: &'a [&'static str]) -> Self {
+        Self {
+            pass_names,
+            passes: Vec::new(),
+            timestamps: None,
+        }
+    }
+
+    pub fn add(&mut self, pass: GraphPass<'a>) {
+        self.passes.push(pass);
+    }
+
+    // Pass i in execution order writes its begin and end timestamps to queries 2i and
+    // 2i + 1 of `query_set`, for the first `max_passes` passes.
+    pub fn time_passes(&mut self, query_set: &'a wgpu::QuerySet, max_passes: u32) {
+        self.timestamps = Some((query_set, max_passes));
+    }
+
+    // Passes in dependency order, ties in the order they were added.
+    fn order(&self) -> anyhow::Result<Vec<usize>> {
+        for pass in &self.passes {
+            let mut names = std::iter::once(&pass.name).chain(&pass.after);
+            if let Some(unknown) = names.find(|n| !self.pass_names.contains(n)) {
+                bail!("pass {:?}: unknown pass name {unknown:?}", pass.name);
+            }
+        }
+
+        let mut waiting_on: Vec<usize> = self
+            .passes
+            .iter()
+            .map(|pass| {
+                pass.after
+                    .iter()
+                    .map(|name| self.passes.iter().filter(|p| p.name == *name).count())
+                    .sum()
+            })
+            .collect();
+
+        let mut order = Vec::with_capacity(self.passes.len());
+        let mut done = vec![false; self.passes.len()];
+        while order.len() < self.passes.len() {
+            let Some(next) = (0..self.passes.len()).find(|&i| !done[i] && waiting_on[i] == 0)
+            else {
+                let stuck = (0..self.passes.len())
+                    .filter(|&i| !done[i])
+                    .map(|i| self.passes[i].name)
+                    .collect::<Vec<_>>();
+                bail!("dependency cycle between passes {stuck:?}");
+            };
+            done[next] = true;
+            order.push(next);
+
+            let name = self.passes[next].name;
+            for (i, pass) in self.passes.iter().enumerate() {
+                waiting_on[i] -= pass.after.iter().filter(|&&after| after == name).count();
+            }
+        }
+        Ok(order)
+    }
+
+    // Returns the names of the passes in the order they were recorded.
+    pub fn execute(
+        self,
+        device: &wgpu::Device,
+        encoder: &mut wgpu::CommandEncoder,
+        transients: &TransientTargets,
+    ) -> anyhow::Result<Vec<&'static str>> {
+        let order = self.order()?;
+        let names = order.iter().map(|&i| self.passes[i].name).collect();
+        let mut passes = self.passes.into_iter().map(Some).collect::<Vec<_>>();
+
+        for (n, i) in order.into_iter().enumerate() {
+            let pass = passes[i].take().expect("every pass is ordered once");
+
+            let transient_views = pass
+                .color_attachments
+                .iter()
+                .map(|attachment| match attachment {
+                    Some(ColorAttachment {
+                        target: Target::Transient(desc),
+                        ..
+                    }) => Some(transients.view(device, *desc)),
+                    _ => None,
+                })
+                .collect::<Vec<_>>();
+            let color_attachments = pass
+                .color_attachments
+                .iter()
+                .zip(&transient_views)
+                .map(|(attachment, transient)| {
+                    attachment
+                        .as_ref()
+                        .map(|attachment| wgpu::RenderPassColorAttachment {
+                            view: match &attachment.target {
+                                Target::View(view) => view,
+                                Target::Transient(_) => transient.as_ref().unwrap(),
+                            },
+                            depth_slice: None,
+                            resolve_target: attachment.resolve_target,
+                            ops: attachment.ops,
+                        })
+                })
+                .collect::<Vec<_>>();
+
+            let timestamp_writes = self
+                .timestamps
+                .filter(|&(_, max_passes)| n < max_passes as usize)
+                .map(|(query_set, _)| wgpu::RenderPassTimestampWrites {
+                    query_set,
+                    beginning_of_pass_write_index: Some(2 * n as u32),
+                    end_of_pass_write_index: Some(2 * n as u32 + 1),
+                });
+
+            // The encoder stays locked until the pass is dropped at the end of the loop
+            let mut render_pass: wgpu::RenderPass<'a> = encoder
+                .begin_render_pass(&wgpu::RenderPassDescriptor {
+                    label: Some(pass.name),
+                    color_attachments: &color_attachments,
+                    depth_stencil_attachment: pass.depth_stencil_attachment,
+                    occlusion_query_set: pass.occlusion_query_set,
+                    timestamp_writes,
+                })
+                .forget_lifetime();
+            (pass.record)(&mut render_pass);
+        }
+
+        transients.end_frame();
+        Ok(names)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Only ordered, never executed
+    fn pass(name: &'static str, after: &[&'static str]) -> GraphPass<'static> {
+        GraphPass::new(name, move |_| unreachable!("{name} recorded")).after(after)
+    }
+
+    const NAMES: &[&str] = &[
+        "a", "b", "c", "surface", "mirror", "mask", "ssr", "scene", "water",
+    ];
+
+    fn order(passes: Vec<GraphPass<'static>>) -> anyhow::Result<Vec<usize>> {
+        let mut graph = RenderGraph::new(NAMES);
+        for pass in passes {
+            graph.add(pass);
+        }
+        graph.order()
+    }
+
+    #[test]
+    fn ties_keep_the_order_added() {
+        let passes = vec![pass("a", &[]), pass("b", &[]), pass("c", &[])];
+        assert_eq!(order(passes).unwrap(), [0, 1, 2]);
+
+        let passes = vec![pass("b", &["a"]), pass("a", &[]), pass("c", &[])];
+        assert_eq!(order(passes).unwrap(), [1, 0, 2]);
+    }
+
+    #[test]
+    fn waits_on_every_pass_of_a_name() {
+        let passes = vec![
+            pass("surface", &["mirror"]),
+            pass("mirror", &[]),
+            pass("mask", &[]),
+            pass("mirror", &["mask"]),
+        ];
+        assert_eq!(order(passes).unwrap(), [1, 2, 3, 0]);
+    }
+
+    #[test]
+    fn a_name_without_passes_is_done() {
+        let passes = vec![pass("ssr", &["scene", "water"]), pass("scene", &[])];
+        assert_eq!(order(passes).unwrap(), [1, 0]);
+    }
+
+    #[test]
+    fn unknown_names_are_errors() {
+        let passes = vec![pass("scene", &[]), pass("ssr", &["secne"])];
+        let error = order(passes).unwrap_err().to_string();
+        assert!(error.contains("\"secne\""), "{error}");
+
+        let passes = vec![pass("sceen", &[])];
+        let error = order(passes).unwrap_err().to_string();
+        assert!(error.contains("\"sceen\""), "{error}");
+    }
+
+    #[test]
+    fn cycles_are_errors() {
+        let passes = vec![pass("a", &["b"]), pass("b", &["a"]), pass("c", &[])];
+        let error = order(passes).unwrap_err().to_string();
+        assert!(error.contains("dependency cycle"), "{error}");
+        assert!(
+            error.contains("\"a\"") && error.contains("\"b\""),
+            "{error}"
+        );
+        assert!(!error.contains("\"c\""), "{error}");
+    }
+}
@@ -0,0 +1,164 @@
+// / S C R E E N S H O T S
+// / The frame after a request is copied into a buffer before it is presented and saved as a
+// / timestamped PNG once the buffer is mapped: right away on native, a frame or two later on
+// / the web, where the render loop can't wait on the GPU.
+
+use std::sync::{Arc, Mutex};
+
+use crate::offscreen::Readback;
+
+#[derive(Default)]
+pub struct Screenshots {
+    requested: bool,
+    pending: Option<Pending>,
+}
+
+struct Pending {
+    readback: Readback,
+    // Set once map_async is called; its outcome, Some(true) once the pixels can be read
+    mapped: Option<Arc<Mutex<Option<bool>>>>,
+}
+
+impl Screenshots {
+    pub fn request(&mut self) {
+        self.requested = true;
+    }
+
+    // Copies `texture`, the finished frame, if a screenshot was asked for. A request made
+    // while the last one is still being read back waits for it.
+    pub fn record(
+        &mut self,
+        device: &wgpu::Device,
+        encoder: &mut wgpu::CommandEncoder,
+        texture: &wgpu::Texture,
+    ) {
+        if !self.requested || self.pending.is_some() {
+            return;
+        }
+        self.requested = false;
+        match Readback::new(device, encoder, texture) {
+            Ok(readback) => {
+                self.pending = Some(Pending {
+                    readback,
+                    mapped: None,
+                })
+            }
+            Err(e) => log::error!("screenshot: {e:#}"),
+        }
+    }
+
+    // Once the frame is submitted: maps the copy and saves it when it is mapped.
+    pub fn finish(&mut self, device: &wgpu::Device) {
+        let Some(pending) = &mut self.pending else {
+            return;
+        };
+        let mapped = pending
+            .mapped
+            .get_or_insert_with(|| {
+                let mapped = Arc::new(Mutex::new(None));
+                let on_mapped = mapped.clone();
+                pending.readback.map(move |result| {
+                    *on_mapped.lock().unwrap() = Some(result.is_ok());
+                });
+                mapped
+            })
+            .clone();
+
+        #[cfg(not(target_arch = "wasm32"))]
+        let _ = device.poll(wgpu::PollType::wait_indefinitely());
+        #[cfg(target_arch = "wasm32")]
+        let _ = device.poll(wgpu::PollType::Poll);
+
+        let Some(ok) = mapped.lock().unwrap().take() else {
+            return;
+        };
+        let readback = self.pending.take().unwrap().readback;
+        if !ok {
+            log::error!("screenshot: the frame could not be read back");
+            return;
+        }
+        match readback.to_rgba().and_then(|image| save(&image)) {
+            Ok(name) => log::info!("screenshot: {name}"),
+            Err(e) => log::error!("screenshot: {e:#}"),
+        }
+    }
+}
+
+// Into the working directory.
+#[cfg(not(target_arch = "wasm32"))]
+fn save(image: &image::RgbaImage) -> anyhow::Result<String> {
+    let name = format!("screenshot-{}.png", timestamp());
+    image.save(&name)?;
+    Ok(name)
+}
+
+// As a browser download.
+#[cfg(target_arch = "wasm32")]
+fn save(image: &image::RgbaImage) -> anyhow::Result<String> {
+    use wasm_bindgen::JsCast;
+
+    let mut png = std::io::Cursor::new(Vec::new());
+    image.write_to(&mut png, image::ImageFormat::Png)?;
+    let bytes = js_sys::Uint8Array::from(png.get_ref().as_slice());
+
+    let js_error = |e: wasm_bindgen::JsValue| anyhow::anyhow!("{e:?}");
+    let options = web_sys::BlobPropertyBag::new();
+    options.set_type("image/png");
+    let blob = web_sys::Blob::new_with_u8_array_sequence_and_options(
+        &js_sys::Array::of1(&bytes),
+        &options,
+    )
+    .map_err(js_error)?;
+    let url = web_sys::Url::create_object_url_with_blob(&blob).map_err(js_error)?;
+
+    let name = format!("screenshot-{}.png", timestamp());
+    let document = web_sys::window()
+        .and_then(|window| window.document())
+        .ok_or_else(|| anyhow::anyhow!("no document"))?;
+    let link: web_sys::HtmlAnchorElement = document
+        .create_element("a")
+        .map_err(js_error)?
+        .unchecked_into();
+    link.set_href(&url);
+    link.set_download(&name);
+    link.click();
+    web_sys::Url::revoke_object_url(&url).map_err(js_error)?;
+    Ok(name)
+}
+
+// YYYYMMDD-HHMMSS-mmm, in UTC: file names that sort by time.
+pub fn timestamp() -> String {
+    let since_epoch = instant::SystemTime::now()
+        .duration_since(instant::SystemTime::UNIX_EPOCH)
+        .unwrap_or_default();
+    let secs = since_epoch.as_secs();
+    let (year, month, day) = civil_from_days((secs / 86_400) as i64);
+    let time = secs % 86_400;
+    format!(
+        "{year:04}{month:02}{day:02}-{:02}{:02}{:02}-{:03}",
+        time / 3600,
+        time / 60 % 60,
+        time % 60,
+        since_epoch.subsec_millis()
+    )
+}
+
+// Days since 1970-01-01 to a (year, month, day) of the proleptic Gregorian calendar
+// (Howard Hinnant's algorithm).
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let day_of_era = z.rem_euclid(146_097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = (day_of_year - (153 * month_index + 2) / 5 + 1) as u32;
+    let month = if month_index < 10 {
+        month_index + 3
+    } else {
+        month_index - 9
+    } as u32;
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+    (year, month, day)
+}
@@ -0,0 +1,78 @@
+use std::{collections::HashMap, path::PathBuf, time::SystemTime};
+
+use instant::{Duration, Instant};
+
+const POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+// Watches src/shaders of the source tree for .wgsl files saved since the last poll.
+// Polling the modification times is enough for a handful of files.
+pub struct ShaderWatcher {
+    dir: PathBuf,
+    modified: HashMap<String, SystemTime>,
+    last_poll: Instant,
+}
+
+impl ShaderWatcher {
+    pub fn new() -> Self {
+        let mut watcher = Self {
+            dir: PathBuf::from(concat!(env!("CARGO_MANIFEST_DIR"), "/src/shaders")),
+            modified: HashMap::new(),
+            last_poll: Instant::now(),
+        };
+        watcher.modified = watcher.scan();
+        watcher
+    }
+
+    pub fn dir(&self) -> &PathBuf {
+        &self.dir
+    }
+
+    // Paths (e.g. "water.wgsl", "common/camera.wgsl") changed since the last poll, with their new text.
+    pub fn poll(&mut self) -> Vec<(String, String)> {
+        if self.last_poll.elapsed() < POLL_INTERVAL {
+            return Vec::new();
+        }
+        self.last_poll = Instant::now();
+
+        let mut changed = Vec::new();
+        for (file, time) in self.scan() {
+            if self.modified.get(&file) == Some(&time) {
+                continue;
+            }
+            // A file that can't be read yet (mid-save) comes back on the next poll
+            if let Ok(text) = std::fs::read_to_string(self.dir.join(&file)) {
+                self.modified.insert(file.clone(), time);
+                changed.push((file, text));
+            }
+        }
+        changed.sort();
+        changed
+    }
+
+    // src/shaders and src/shaders/common, by path under src/shaders
+    fn scan(&self) -> HashMap<String, SystemTime> {
+        let mut files = HashMap::new();
+        for sub in ["", "common/"] {
+            let Ok(entries) = std::fs::read_dir(self.dir.join(sub)) else {
+                continue;
+            };
+            files.extend(
+                entries
+                    .flatten()
+                    .filter(|entry| entry.path().extension().is_some_and(|ext| ext == "wgsl"))
+                    .filter_map(|entry| {
+                        let time = entry.metadata().and_then(|m| m.modified()).ok()?;
+                        let name = entry.file_name();
+                        Some((format!("{sub}{}", name.to_string_lossy()), time))
+                    }),
+            );
+        }
+        files
+    }
+}
+
+impl Default for ShaderWatcher {
+    fn default() -> Self {
+        Self::new()
+    }
+}
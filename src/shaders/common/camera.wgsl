@@ -0,0 +1,6 @@
+// camera::CameraUniform
+struct CameraUniform {
+    view_proj: mat4x4<f32>,
+    // Eye position, for view-dependent shading
+    view_position: vec4<f32>,
+};
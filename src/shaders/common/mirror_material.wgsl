@@ -0,0 +1,8 @@
+// extra::MirrorMaterialUniform
+struct MirrorMaterial {
+    tint: vec3<f32>,
+    reflectance: f32, // F0
+    roughness: f32,
+    dirt_strength: f32,
+    scratch_strength: f32,
+};
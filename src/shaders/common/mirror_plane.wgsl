@@ -0,0 +1,7 @@
+// extra::MirrorPlaneUniform
+struct MirrorPlane {
+    normal: vec3<f32>,
+    _pad1: f32,
+    point: vec3<f32>,
+    clip_discard: u32, // 1 when the reflected camera has no oblique near plane
+};
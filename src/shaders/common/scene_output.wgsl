@@ -0,0 +1,6 @@
+// Main scene pass: color plus the normal/reflectivity target for screen-space reflections
+// (ssr::NORMAL_FORMAT)
+struct SceneOutput {
+    @location(0) color: vec4<f32>,
+    @location(1) normal: vec4<f32>,
+}
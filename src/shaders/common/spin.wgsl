@@ -0,0 +1,4 @@
+// extra::SpinUniform
+struct SpinUniform {
+    model: mat4x4<f32>,
+}
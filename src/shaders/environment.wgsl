@@ -0,0 +1,56 @@
+// Vertex shader
+#import "common/camera.wgsl"
+@group(1) @binding(0)
+var<uniform> camera: CameraUniform;
+
+struct VertexInput {
+    @location(0) position: vec3<f32>,
+    @location(1) tex_coords: vec2<f32>, // Unused
+    @location(2) normal: vec3<f32>,
+};
+
+#import "common/instance.wgsl"
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) world_normal: vec3<f32>,
+    @location(1) view_direction: vec3<f32>,
+};
+
+@vertex
+fn vs_main(
+    model: VertexInput,
+    instance: InstanceInput,
+) -> VertexOutput {
+    let model_matrix = instance_model_matrix(instance);
+    let world_position = model_matrix * vec4<f32>(model.position, 1.0);
+
+    var out: VertexOutput;
+    out.clip_position = camera.view_proj * world_position;
+    // Uniform scale only, so the model matrix is fine for normals
+    out.world_normal = (model_matrix * vec4<f32>(model.normal, 0.0)).xyz;
+    out.view_direction = world_position.xyz - camera.view_position.xyz;
+    return out;
+}
+
+// Fragment shader
+@group(0) @binding(0)
+var t_environment: texture_cube<f32>;
+@group(0) @binding(1)
+var s_environment: sampler;
+
+#import "common/scene_output.wgsl"
+
+@fragment
+fn fs_main(in: VertexOutput) -> SceneOutput {
+    let normal = normalize(in.world_normal);
+    let reflected = reflect(normalize(in.view_direction), normal);
+    let color = textureSample(t_environment, s_environment, reflected).rgb;
+
+    var out: SceneOutput;
+    // Slightly dark chrome
+    out.color = vec4<f32>(color * 0.9, 1.0);
+    // already reflective, no screen-space reflection on top
+    out.normal = vec4<f32>(normal * 0.5 + 0.5, 0.0);
+    return out;
+}
@@ -1,84 +0,0 @@
-// Vertex shader
-struct CameraUniform {
-    view_proj: mat4x4<f32>,
-};
-@group(1) @binding(0) // 1.
-var<uniform> camera: CameraUniform;
-
-struct SpinUniform {
-    model:  mat4x4<f32>,
-}
-@group(2) @binding(0)
-var<uniform> spin: SpinUniform;
-
-
-struct MirrorPlane {
-    normal: vec3<f32>,
-    _pad1: f32,
-    point: vec3<f32>,
-    _pad2: f32,
-};
-@group(3) @binding(0)
-var<uniform> mirror: MirrorPlane;
-
-struct VertexInput {
-    @location(0) position: vec3<f32>,
-    @location(1) tex_coords: vec2<f32>,
-};
-
-struct VertexOutput {
-    @builtin(position) clip_position: vec4<f32>,
-    @location(0) tex_coords: vec2<f32>,
-}
-
-struct InstanceInput {
-    @location(5) model_matrix_0: vec4<f32>,
-    @location(6) model_matrix_1: vec4<f32>,
-    @location(7) model_matrix_2: vec4<f32>,
-    @location(8) model_matrix_3: vec4<f32>,
-};
-
-
-@vertex
-fn vs_main(
-    model: VertexInput,
-    instance: InstanceInput,
-) -> VertexOutput {
-
-    var out: VertexOutput;
-    // reassemble the matrix
-    let model_matrix = mat4x4<f32>(
-        instance.model_matrix_0,
-        instance.model_matrix_1,
-        instance.model_matrix_2,
-        instance.model_matrix_3,
-    );
-
-    let world_pos = model.position ;
-    // let eps = 1e-4;
-    // // ---- CLIPPING ----
-    // let distance = dot(mirror.normal, world_pos - mirror.point);
-    // if (distance > eps) {
-    //     // clip vertex
-    //     // Move vertex outside clip space
-    //     out.clip_position = vec4<f32>(0.0, 0.0, 2.0, 1.0);
-    //     return out;
-    // }
-
-    out.tex_coords = model.tex_coords;
-    out.clip_position = camera.view_proj * model_matrix * spin.model * vec4<f32>(model.position, 1.0);
-    return out;
-}
- //  
-
-// Fragment shader
-@group(0) @binding(0)
-var t_diffuse: texture_2d<f32>;
-@group(0) @binding(1)
-var s_diffuse: sampler;
-
-@fragment
-fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
-    return textureSample(t_diffuse, s_diffuse, in.tex_coords);
-}
- 
\ No newline at end of file
@@ -1,47 +1,90 @@
-// simple mirror surface
+// mirror surface: Fresnel reflectance, tint, dirt and scratches over the stencil reflection
+//
+// Drawn twice per mirror:
+// fs_reflect scales the reflection already in the framebuffer (multiplicative blend),
+// fs_coat then adds the light the coating scatters itself (additive blend).
 
-struct CameraUniform {
-    view_proj: mat4x4<f32>,
-};
+#import "common/camera.wgsl"
 @group(0) @binding(0) // 1.
 var<uniform> camera: CameraUniform;
 
+#import "common/mirror_material.wgsl"
+@group(1) @binding(0)
+var<uniform> material: MirrorMaterial;
+@group(1) @binding(1)
+var t_dirt: texture_2d<f32>;
+@group(1) @binding(2)
+var t_scratches: texture_2d<f32>;
+@group(1) @binding(3)
+var s_material: sampler;
+
+#import "common/mirror_plane.wgsl"
+@group(2) @binding(0)
+var<uniform> mirror: MirrorPlane;
+
 struct VertexInput {
     @location(0) position: vec3<f32>,
-    @location(1) tex_coords: vec2<f32>, // Unused 
+    @location(1) tex_coords: vec2<f32>,
 };
 
 struct VertexOutput {
     @builtin(position) clip_position: vec4<f32>,
+    @location(0) tex_coords: vec2<f32>,
+    @location(1) view_direction: vec3<f32>,
 };
 
-struct InstanceInput {
-    @location(5) model_matrix_0: vec4<f32>,
-    @location(6) model_matrix_1: vec4<f32>,
-    @location(7) model_matrix_2: vec4<f32>,
-    @location(8) model_matrix_3: vec4<f32>,
-};
+#import "common/instance.wgsl"
 
 @vertex
 fn vs_main(in: VertexInput, instance: InstanceInput) -> VertexOutput {
     var out: VertexOutput;
 
-        // reassemble the matrix
-    let model_matrix = mat4x4<f32>(
-        instance.model_matrix_0,
-        instance.model_matrix_1,
-        instance.model_matrix_2,
-        instance.model_matrix_3,
-    );
+    let model_matrix = instance_model_matrix(instance);
 
-    out.clip_position = camera.view_proj * model_matrix * vec4<f32>(in.position, 1.0);
+    let world_position = model_matrix * vec4<f32>(in.position, 1.0);
+    out.clip_position = camera.view_proj * world_position;
+    out.tex_coords = in.tex_coords;
+    out.view_direction = camera.view_position.xyz - world_position.xyz;
+    return out;
+}
+
+struct Surface {
+    // Share of the reflection that reaches the eye, per channel
+    reflected: vec3<f32>,
+    // Light scattered by the coating itself
+    coat: vec3<f32>,
+};
+
+fn shade(in: VertexOutput) -> Surface {
+    let dirt = textureSample(t_dirt, s_material, in.tex_coords).r * material.dirt_strength;
+    let scratch = textureSample(t_scratches, s_material, in.tex_coords).r * material.scratch_strength;
+
+    // Schlick's approximation; the back of the mirror reflects nothing
+    let cos_theta = dot(normalize(mirror.normal), normalize(in.view_direction));
+    let f0 = material.reflectance;
+    let fresnel = select(0.0, f0 + (1.0 - f0) * pow(1.0 - saturate(cos_theta), 5.0), cos_theta > 0.0);
+
+    // The stencil reflection cannot be blurred here, so roughness turns into haze
+    let clear = (1.0 - dirt) * (1.0 - scratch) * (1.0 - 0.5 * material.roughness);
+    let specular = fresnel * clear;
+
+    let haze = vec3<f32>(0.5, 0.5, 0.55);
+    let smudge = vec3<f32>(0.35, 0.32, 0.28);
+    let groove = vec3<f32>(0.85, 0.85, 0.85);
+    let base = mix(mix(haze, smudge, saturate(dirt)), groove, saturate(scratch));
+
+    var out: Surface;
+    out.reflected = material.tint * specular;
+    out.coat = base * (1.0 - specular);
     return out;
 }
 
 @fragment
-fn fs_main() -> @location(0) vec4<f32> {
+fn fs_reflect(in: VertexOutput) -> @location(0) vec4<f32> {
+    return vec4<f32>(shade(in).reflected, 1.0);
+}
 
-    let reflectivity = 0.75 ;// or Fresnel
-    // Simple mirror tint
-    return vec4<f32>(0.5, 0.5, 0.55, reflectivity);
+@fragment
+fn fs_coat(in: VertexOutput) -> @location(0) vec4<f32> {
+    return vec4<f32>(shade(in).coat, 0.0);
 }
@@ -1,27 +1,38 @@
-//
+// mirror surface sampling the reflection texture (MirrorTechnique::RenderTexture)
+// Same material as mirror_surface.wgsl, but roughness can blur the reflection here.
 
-struct CameraUniform {
-    view_proj: mat4x4<f32>,
-};
+#import "common/camera.wgsl"
 @group(1) @binding(0) // 1.
 var<uniform> camera: CameraUniform;
 
+#import "common/mirror_material.wgsl"
+@group(2) @binding(0)
+var<uniform> material: MirrorMaterial;
+@group(2) @binding(1)
+var t_dirt: texture_2d<f32>;
+@group(2) @binding(2)
+var t_scratches: texture_2d<f32>;
+@group(2) @binding(3)
+var s_material: sampler;
+
+#import "common/mirror_plane.wgsl"
+@group(3) @binding(0)
+var<uniform> mirror: MirrorPlane;
+
 struct VertexInput {
     @location(0) position: vec3<f32>,
-    @location(1) uv: vec2<f32>,
+    @location(1) tex_coords: vec2<f32>,
 };
 
 struct VertexOutput {
     @builtin(position) clip_position: vec4<f32>,
-    @location(0) uv: vec2<f32>,
+    // Same as clip_position, divided by w per fragment (projective texturing)
+    @location(0) screen_position: vec4<f32>,
+    @location(1) tex_coords: vec2<f32>,
+    @location(2) view_direction: vec3<f32>,
 };
 
-struct InstanceInput {
-    @location(5) model_matrix_0: vec4<f32>,
-    @location(6) model_matrix_1: vec4<f32>,
-    @location(7) model_matrix_2: vec4<f32>,
-    @location(8) model_matrix_3: vec4<f32>,
-};
+#import "common/instance.wgsl"
 
 @group(0) @binding(0)
 var mirror_texture: texture_2d<f32>;
@@ -33,24 +44,56 @@ var mirror_sampler: sampler;
 // Vertex shader
 
 @vertex
-fn vs_main(in: VertexInput) -> VertexOutput {
+fn vs_main(in: VertexInput, instance: InstanceInput) -> VertexOutput {
     var out: VertexOutput;
-        // reassemble the matrix
-    let model_matrix = mat4x4<f32>(
-        instance.model_matrix_0,
-        instance.model_matrix_1,
-        instance.model_matrix_2,
-        instance.model_matrix_3,
-    );
-
-    out.clip_position =  camera.view_proj * model_matrix * vec4<f32>(in.position, 1.0);
-    out.uv = in.uv;
+    let model_matrix = instance_model_matrix(instance);
+
+    let world_position = model_matrix * vec4<f32>(in.position, 1.0);
+    out.clip_position = camera.view_proj * world_position;
+    out.screen_position = out.clip_position;
+    out.tex_coords = in.tex_coords;
+    out.view_direction = camera.view_position.xyz - world_position.xyz;
     return out;
 }
 
 // Fragment shader
 
+// Golden-angle spiral, radius 1
+const BLUR_TAPS: u32 = 12u;
+
+fn blurred_reflection(uv: vec2<f32>) -> vec3<f32> {
+    let radius = material.roughness * 0.02;
+    var sum = textureSample(mirror_texture, mirror_sampler, uv).rgb;
+    for (var i = 0u; i < BLUR_TAPS; i++) {
+        let r = sqrt((f32(i) + 0.5) / f32(BLUR_TAPS));
+        let angle = f32(i) * 2.39996;
+        let offset = vec2<f32>(cos(angle), sin(angle)) * r * radius;
+        sum += textureSampleLevel(mirror_texture, mirror_sampler, uv + offset, 0.0).rgb;
+    }
+    return sum / f32(BLUR_TAPS + 1u);
+}
+
 @fragment
 fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
-    return textureSample(mirror_texture, mirror_sampler, in.uv);
+    // NDC -> texture coordinates (y points down in texture space)
+    let ndc = in.screen_position.xy / in.screen_position.w;
+    let uv = vec2<f32>(ndc.x * 0.5 + 0.5, 0.5 - ndc.y * 0.5);
+
+    let reflected = blurred_reflection(uv);
+    let dirt = textureSample(t_dirt, s_material, in.tex_coords).r * material.dirt_strength;
+    let scratch = textureSample(t_scratches, s_material, in.tex_coords).r * material.scratch_strength;
+
+    // Schlick's approximation; the back of the mirror reflects nothing
+    let cos_theta = dot(normalize(mirror.normal), normalize(in.view_direction));
+    let f0 = material.reflectance;
+    let fresnel = select(0.0, f0 + (1.0 - f0) * pow(1.0 - saturate(cos_theta), 5.0), cos_theta > 0.0);
+
+    let specular = fresnel * (1.0 - dirt) * (1.0 - scratch);
+
+    let haze = vec3<f32>(0.5, 0.5, 0.55);
+    let smudge = vec3<f32>(0.35, 0.32, 0.28);
+    let groove = vec3<f32>(0.85, 0.85, 0.85);
+    let base = mix(mix(haze, smudge, saturate(dirt)), groove, saturate(scratch));
+
+    return vec4<f32>(reflected * material.tint * specular + base * (1.0 - specular), 1.0);
 }
@@ -0,0 +1,29 @@
+// Reflections rendered at reduced resolution (ReflectionQuality), stretched over the
+// framebuffer. The stencil test keeps them inside the mirrors.
+
+@group(0) @binding(0)
+var t_reflection: texture_2d<f32>;
+@group(0) @binding(1)
+var s_reflection: sampler;
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) uv: vec2<f32>,
+};
+
+// Fullscreen triangle
+@vertex
+fn vs_main(@builtin(vertex_index) vertex_index: u32) -> VertexOutput {
+    let uv = vec2<f32>(f32((vertex_index << 1u) & 2u), f32(vertex_index & 2u));
+
+    var out: VertexOutput;
+    out.clip_position = vec4<f32>(uv.x * 2.0 - 1.0, 1.0 - uv.y * 2.0, 0.0, 1.0);
+    out.uv = uv;
+    return out;
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    // Bilinear filtering does the upsampling
+    return textureSampleLevel(t_reflection, s_reflection, in.uv, 0.0);
+}
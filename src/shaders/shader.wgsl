@@ -1,42 +1,43 @@
+// The textured scene. With MIRROR_CLIP it is the reflected scene instead: group 3 holds
+// the mirror plane, and fragments behind the mirror are discarded when the reflected
+// camera has no oblique near plane.
+
 // Vertex shader
-struct CameraUniform {
-    view_proj: mat4x4<f32>,
-};
+#import "common/camera.wgsl"
 @group(1) @binding(0) // 1.
 var<uniform> camera: CameraUniform;
 
-struct SpinUniform {
-    model:  mat4x4<f32>,
-}
+#import "common/spin.wgsl"
 @group(2) @binding(0)
 var<uniform> spin: SpinUniform;
 
-
-struct MirrorPlane {
-    normal: vec3<f32>,
-    _pad1: f32,
-    point: vec3<f32>,
-    _pad2: f32,
-};
+#ifdef MIRROR_CLIP
+#import "common/mirror_plane.wgsl"
 @group(3) @binding(0)
 var<uniform> mirror: MirrorPlane;
+#else
+// Only read by fs_scene
+struct SurfaceUniform {
+    reflectivity: f32,
+}
+@group(3) @binding(0)
+var<uniform> surface: SurfaceUniform;
+#endif
 
 struct VertexInput {
     @location(0) position: vec3<f32>,
     @location(1) tex_coords: vec2<f32>,
+    @location(2) normal: vec3<f32>,
 };
 
 struct VertexOutput {
     @builtin(position) clip_position: vec4<f32>,
     @location(0) tex_coords: vec2<f32>,
+    @location(1) world_normal: vec3<f32>,
+    @location(2) world_position: vec3<f32>,
 }
 
-struct InstanceInput {
-    @location(5) model_matrix_0: vec4<f32>,
-    @location(6) model_matrix_1: vec4<f32>,
-    @location(7) model_matrix_2: vec4<f32>,
-    @location(8) model_matrix_3: vec4<f32>,
-};
+#import "common/instance.wgsl"
 
 
 @vertex
@@ -46,27 +47,15 @@ fn vs_main(
 ) -> VertexOutput {
 
     var out: VertexOutput;
-    // reassemble the matrix
-    let model_matrix = mat4x4<f32>(
-        instance.model_matrix_0,
-        instance.model_matrix_1,
-        instance.model_matrix_2,
-        instance.model_matrix_3,
-    );
+    let model_matrix = instance_model_matrix(instance);
 
-    // let world_pos = model.position ;
-    // let eps = 1e-4;
-    // // ---- CLIPPING ----
-    // let distance = dot(mirror.normal, world_pos - mirror.point);
-    // if (distance < eps) {
-    //     // clip vertex
-    //     // Move vertex outside clip space
-    //     out.clip_position = vec4<f32>(0.0, 0.0, 2.0, 1.0);
-    //     return out;
-    // }
+    // World position before the reflection (camera.view_proj holds the reflection)
+    let world_position = model_matrix * spin.model * vec4<f32>(model.position, 1.0);
 
     out.tex_coords = model.tex_coords;
-    out.clip_position = camera.view_proj * model_matrix * spin.model * vec4<f32>(model.position, 1.0);
+    out.world_normal = (model_matrix * spin.model * vec4<f32>(model.normal, 0.0)).xyz;
+    out.world_position = world_position.xyz;
+    out.clip_position = camera.view_proj * world_position;
     return out;
 }
  //  
@@ -79,6 +68,25 @@ var s_diffuse: sampler;
 
 @fragment
 fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+#ifdef MIRROR_CLIP
+    // ---- CLIPPING ----
+    // Fallback for adapters without the oblique near plane: anything behind the
+    // mirror must not show up in the reflection.
+    if (mirror.clip_discard != 0u && dot(mirror.normal, in.world_position - mirror.point) < 0.0) {
+        discard;
+    }
+#endif
     return textureSample(t_diffuse, s_diffuse, in.tex_coords);
 }
- 
\ No newline at end of file
+
+#ifndef MIRROR_CLIP
+#import "common/scene_output.wgsl"
+
+@fragment
+fn fs_scene(in: VertexOutput) -> SceneOutput {
+    var out: SceneOutput;
+    out.color = textureSample(t_diffuse, s_diffuse, in.tex_coords);
+    out.normal = vec4<f32>(normalize(in.world_normal) * 0.5 + 0.5, surface.reflectivity);
+    return out;
+}
+#endif
@@ -0,0 +1,16 @@
+// Drawn over the frame while a hot-reloaded shader fails to compile: red warning
+// stripes, the error itself is in the window title and the log.
+
+// Fullscreen triangle
+@vertex
+fn vs_main(@builtin(vertex_index) vertex_index: u32) -> @builtin(position) vec4<f32> {
+    let uv = vec2<f32>(f32((vertex_index << 1u) & 2u), f32(vertex_index & 2u));
+    return vec4<f32>(uv.x * 2.0 - 1.0, 1.0 - uv.y * 2.0, 0.0, 1.0);
+}
+
+@fragment
+fn fs_main(@builtin(position) position: vec4<f32>) -> @location(0) vec4<f32> {
+    let stripe = (u32(position.x + position.y) / 24u) % 2u;
+    let alpha = select(0.08, 0.25, stripe == 0u);
+    return vec4<f32>(1.0, 0.0, 0.0, alpha);
+}
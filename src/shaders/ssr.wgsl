@@ -0,0 +1,101 @@
+// Screen-space reflections, composited over the finished frame.
+// The depth is bound as unfilterable float (GLSL can't load from depth textures), and
+// is a multisampled texture when MSAA is defined; textureLoad reads sample 0 then.
+
+struct SsrUniform {
+    view_proj: mat4x4<f32>,
+    inv_view_proj: mat4x4<f32>,
+    eye: vec4<f32>,
+    max_distance: f32,
+    thickness: f32,
+    steps: u32,
+    _pad: u32,
+};
+
+@group(0) @binding(0)
+var t_scene: texture_2d<f32>;
+@group(0) @binding(1)
+var s_scene: sampler;
+@group(0) @binding(2)
+#ifdef MSAA
+var t_depth: texture_multisampled_2d<f32>;
+#else
+var t_depth: texture_2d<f32>;
+#endif
+@group(0) @binding(3)
+var t_normal: texture_2d<f32>;
+@group(0) @binding(4)
+var<uniform> ssr: SsrUniform;
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) uv: vec2<f32>,
+};
+
+// Fullscreen triangle
+@vertex
+fn vs_main(@builtin(vertex_index) vertex_index: u32) -> VertexOutput {
+    let uv = vec2<f32>(f32((vertex_index << 1u) & 2u), f32(vertex_index & 2u));
+
+    var out: VertexOutput;
+    out.clip_position = vec4<f32>(uv.x * 2.0 - 1.0, 1.0 - uv.y * 2.0, 0.0, 1.0);
+    out.uv = uv;
+    return out;
+}
+
+fn world_from_screen(uv: vec2<f32>, depth: f32) -> vec3<f32> {
+    let ndc = vec4<f32>(uv.x * 2.0 - 1.0, 1.0 - uv.y * 2.0, depth, 1.0);
+    let world = ssr.inv_view_proj * ndc;
+    return world.xyz / world.w;
+}
+
+fn depth_at(uv: vec2<f32>) -> f32 {
+    let size = vec2<f32>(textureDimensions(t_depth));
+    let pixel = clamp(vec2<i32>(uv * size), vec2<i32>(0), vec2<i32>(size) - 1);
+    return textureLoad(t_depth, pixel, 0).r;
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    let scene = textureSample(t_scene, s_scene, in.uv);
+
+    let pixel = vec2<i32>(in.clip_position.xy);
+    let surface = textureLoad(t_normal, pixel, 0);
+    let depth = depth_at(in.uv);
+    if (surface.a <= 0.0 || depth >= 1.0) {
+        return scene;
+    }
+
+    let origin = world_from_screen(in.uv, depth);
+    let normal = normalize(surface.xyz * 2.0 - 1.0);
+    let ray = reflect(normalize(origin - ssr.eye.xyz), normal);
+    let step = ssr.max_distance / f32(ssr.steps);
+
+    var reflection = vec3<f32>(0.0);
+    var strength = 0.0;
+    for (var i = 1u; i <= ssr.steps; i++) {
+        let position = origin + ray * step * f32(i);
+        let clip = ssr.view_proj * vec4<f32>(position, 1.0);
+        if (clip.w <= 0.0) {
+            break;
+        }
+        let ndc = clip.xyz / clip.w;
+        let uv = vec2<f32>(ndc.x * 0.5 + 0.5, 0.5 - ndc.y * 0.5);
+        if (any(uv < vec2<f32>(0.0)) || any(uv > vec2<f32>(1.0))) {
+            break;
+        }
+
+        // Behind the depth buffer, but not by more than `thickness`
+        let hit = world_from_screen(uv, depth_at(uv));
+        let behind = distance(ssr.eye.xyz, position) - distance(ssr.eye.xyz, hit);
+        if (behind > 0.0 && behind < ssr.thickness) {
+            reflection = textureSampleLevel(t_scene, s_scene, uv, 0.0).rgb;
+            // Fade out towards the screen edges and the end of the ray
+            let edge = 1.0 - smoothstep(0.8, 1.0, max(abs(ndc.x), abs(ndc.y)));
+            strength = edge * (1.0 - f32(i) / f32(ssr.steps));
+            break;
+        }
+    }
+
+    return vec4<f32>(mix(scene.rgb, reflection, surface.a * strength), scene.a);
+}
@@ -0,0 +1,54 @@
+// Frame stats over the top left corner: a grid of 3x5 pixel glyphs on a dark box. The
+// glyph bits of every cell come from the CPU (stats_overlay.rs).
+
+const COLUMNS: u32 = 48u;
+// A glyph and one pixel of spacing, in glyph pixels
+const CELL: vec2<u32> = vec2<u32>(4u, 6u);
+// Between the box and the frame's corner, and around the text, in screen pixels
+const MARGIN: u32 = 8u;
+const PADDING: u32 = 6u;
+
+struct StatsText {
+    // Columns and rows in use, screen pixels per glyph pixel
+    grid: vec4<u32>,
+    glyphs: array<vec4<u32>, 240>,
+}
+
+@group(0) @binding(0)
+var<uniform> text: StatsText;
+
+// Fullscreen triangle
+@vertex
+fn vs_main(@builtin(vertex_index) vertex_index: u32) -> @builtin(position) vec4<f32> {
+    let uv = vec2<f32>(f32((vertex_index << 1u) & 2u), f32(vertex_index & 2u));
+    return vec4<f32>(uv.x * 2.0 - 1.0, 1.0 - uv.y * 2.0, 0.0, 1.0);
+}
+
+@fragment
+fn fs_main(@builtin(position) position: vec4<f32>) -> @location(0) vec4<f32> {
+    let scale = text.grid.z;
+    let size = text.grid.xy * CELL * scale;
+    let pixel = vec2<u32>(position.xy);
+    if any(pixel < vec2<u32>(MARGIN)) || any(pixel >= vec2<u32>(MARGIN + 2u * PADDING) + size) {
+        discard;
+    }
+
+    let background = vec4<f32>(0.0, 0.0, 0.0, 0.6);
+    if any(pixel < vec2<u32>(MARGIN + PADDING)) {
+        return background;
+    }
+    let text_pixel = (pixel - vec2<u32>(MARGIN + PADDING)) / scale;
+    let cell = text_pixel / CELL;
+    let inside = text_pixel % CELL;
+    if any(cell >= text.grid.xy) || inside.x >= 3u || inside.y >= 5u {
+        return background;
+    }
+
+    let index = cell.y * COLUMNS + cell.x;
+    let glyph = text.glyphs[index / 4u][index % 4u];
+    let bit = 14u - (inside.y * 3u + inside.x);
+    if ((glyph >> bit) & 1u) == 1u {
+        return vec4<f32>(1.0, 1.0, 1.0, 1.0);
+    }
+    return background;
+}
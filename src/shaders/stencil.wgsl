@@ -1,7 +1,5 @@
 
-struct CameraUniform {
-    view_proj: mat4x4<f32>,
-};
+#import "common/camera.wgsl"
 @group(0) @binding(0) // 1.
 var<uniform> camera: CameraUniform;
 
@@ -10,26 +8,21 @@ struct VertexInput {
     @location(1) tex_coords: vec2<f32>, // Unused 
 };
 
-struct InstanceInput {
-    @location(5) model_matrix_0: vec4<f32>,
-    @location(6) model_matrix_1: vec4<f32>,
-    @location(7) model_matrix_2: vec4<f32>,
-    @location(8) model_matrix_3: vec4<f32>,
-};
+#import "common/instance.wgsl"
 
 @vertex
 fn vs_main(in: VertexInput,  instance: InstanceInput,) -> @builtin(position) vec4<f32> {
 
-    // reassemble the matrix
-    let model_matrix = mat4x4<f32>(
-        instance.model_matrix_0,
-        instance.model_matrix_1,
-        instance.model_matrix_2,
-        instance.model_matrix_3,
-    );
+    let model_matrix = instance_model_matrix(instance);
 
 
     return camera.view_proj * model_matrix * vec4<f32>(in.position, 1.0);
 }
 
- 
\ No newline at end of file
+ 
+// Only used when the mask is drawn inside a pass with a color target (nested mirrors).
+// The pipeline masks out every color write.
+@fragment
+fn fs_main() -> @location(0) vec4<f32> {
+    return vec4<f32>(0.0, 0.0, 0.0, 0.0);
+}
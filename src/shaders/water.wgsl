@@ -0,0 +1,103 @@
+// Planar water: reflection and refraction textures mixed with Fresnel,
+// both distorted by a scrolling normal map.
+
+#import "common/camera.wgsl"
+@group(1) @binding(0)
+var<uniform> camera: CameraUniform;
+
+@group(0) @binding(0)
+var t_reflection: texture_2d<f32>;
+@group(0) @binding(1)
+var t_refraction: texture_2d<f32>;
+@group(0) @binding(2)
+var s_screen: sampler;
+@group(0) @binding(3)
+var t_normal: texture_2d<f32>;
+@group(0) @binding(4)
+var s_normal: sampler;
+
+struct Water {
+    tint: vec3<f32>,
+    reflectance: f32, // F0
+    time: f32,
+    wave_speed: f32,
+    distortion: f32,
+};
+@group(0) @binding(5)
+var<uniform> water: Water;
+
+struct VertexInput {
+    @location(0) position: vec3<f32>,
+    @location(1) tex_coords: vec2<f32>,
+    @location(2) normal: vec3<f32>,
+};
+
+#import "common/instance.wgsl"
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    // Same as clip_position, divided by w per fragment (projective texturing)
+    @location(0) screen_position: vec4<f32>,
+    @location(1) tex_coords: vec2<f32>,
+    @location(2) view_direction: vec3<f32>,
+    // Tangent frame of the surface: x and z of the mesh, and its normal
+    @location(3) tangent: vec3<f32>,
+    @location(4) bitangent: vec3<f32>,
+    @location(5) normal: vec3<f32>,
+};
+
+@vertex
+fn vs_main(in: VertexInput, instance: InstanceInput) -> VertexOutput {
+    var out: VertexOutput;
+    let model_matrix = instance_model_matrix(instance);
+
+    let world_position = model_matrix * vec4<f32>(in.position, 1.0);
+    out.clip_position = camera.view_proj * world_position;
+    out.screen_position = out.clip_position;
+    out.tex_coords = in.tex_coords;
+    out.view_direction = camera.view_position.xyz - world_position.xyz;
+    out.tangent = (model_matrix * vec4<f32>(1.0, 0.0, 0.0, 0.0)).xyz;
+    out.bitangent = (model_matrix * vec4<f32>(0.0, 0.0, 1.0, 0.0)).xyz;
+    out.normal = (model_matrix * vec4<f32>(in.normal, 0.0)).xyz;
+    return out;
+}
+
+#import "common/scene_output.wgsl"
+
+fn sample_normal(uv: vec2<f32>) -> vec3<f32> {
+    return textureSample(t_normal, s_normal, uv).xyz * 2.0 - 1.0;
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> SceneOutput {
+    // Two layers of the normal map scrolling in different directions
+    let scroll = water.time * water.wave_speed;
+    let waves = normalize(
+        sample_normal(in.tex_coords + vec2<f32>(scroll, 0.6 * scroll))
+            + sample_normal(in.tex_coords * 1.7 - vec2<f32>(0.4 * scroll, scroll))
+    );
+    // Normal map y points along the mesh -z (texture v)
+    let normal = normalize(
+        normalize(in.tangent) * waves.x
+            - normalize(in.bitangent) * waves.y
+            + normalize(in.normal) * waves.z
+    );
+
+    // NDC -> texture coordinates (y points down in texture space)
+    let ndc = in.screen_position.xy / in.screen_position.w;
+    let uv = vec2<f32>(ndc.x * 0.5 + 0.5, 0.5 - ndc.y * 0.5);
+    let offset = waves.xy * water.distortion;
+    let reflected = textureSample(t_reflection, s_screen, clamp(uv + offset, vec2(0.0), vec2(1.0))).rgb;
+    let refracted = textureSample(t_refraction, s_screen, clamp(uv - offset, vec2(0.0), vec2(1.0))).rgb;
+
+    // Schlick's approximation; from below there is only the refraction
+    let cos_theta = dot(normal, normalize(in.view_direction));
+    let f0 = water.reflectance;
+    let fresnel = select(0.0, f0 + (1.0 - f0) * pow(1.0 - saturate(cos_theta), 5.0), cos_theta > 0.0);
+
+    var out: SceneOutput;
+    out.color = vec4<f32>(mix(refracted * water.tint, reflected, fresnel), 1.0);
+    // Reflects on its own, nothing for screen-space reflections to add
+    out.normal = vec4<f32>(normal * 0.5 + 0.5, 0.0);
+    return out;
+}
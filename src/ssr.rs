@@ -0,0 +1,260 @@
+use cgmath::SquareMatrix;
+use wgpu::util::DeviceExt;
+
+use crate::{camera::Camera, depth_stencil::StencilTexture, texture::Texture};
+
+// World normal (xyz * 0.5 + 0.5) and reflectivity (a), written by the main scene pass.
+pub const NORMAL_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba8Unorm;
+
+// Second color target of the main scene pass.
+pub struct NormalTarget {
+    #[allow(unused)]
+    pub texture: wgpu::Texture,
+    pub view: wgpu::TextureView,
+    // Rendered into when MSAA is on, then resolved into `view`
+    pub multisampled_view: Option<wgpu::TextureView>,
+}
+
+impl NormalTarget {
+    pub fn new(
+        device: &wgpu::Device,
+        config: &wgpu::SurfaceConfiguration,
+        sample_count: u32,
+    ) -> Self {
+        let descriptor = |sample_count, usage| wgpu::TextureDescriptor {
+            label: Some("normal_target"),
+            size: wgpu::Extent3d {
+                width: config.width.max(1),
+                height: config.height.max(1),
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count,
+            dimension: wgpu::TextureDimension::D2,
+            format: NORMAL_FORMAT,
+            usage,
+            view_formats: &[],
+        };
+
+        let texture = device.create_texture(&descriptor(
+            1,
+            wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING,
+        ));
+        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+        let multisampled_view = (sample_count > 1).then(|| {
+            device
+                // Only ever resolved, never sampled
+                .create_texture(&descriptor(
+                    sample_count,
+                    wgpu::TextureUsages::RENDER_ATTACHMENT,
+                ))
+                .create_view(&wgpu::TextureViewDescriptor::default())
+        });
+
+        Self {
+            texture,
+            view,
+            multisampled_view,
+        }
+    }
+
+    pub fn color_attachment(&self) -> wgpu::RenderPassColorAttachment<'_> {
+        let (view, resolve_target) = match &self.multisampled_view {
+            Some(multisampled_view) => (multisampled_view, Some(&self.view)),
+            None => (&self.view, None),
+        };
+        wgpu::RenderPassColorAttachment {
+            view,
+            depth_slice: None,
+            resolve_target,
+            ops: wgpu::Operations {
+                load: wgpu::LoadOp::Clear(wgpu::Color::TRANSPARENT),
+                store: wgpu::StoreOp::Store,
+            },
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct SsrUniform {
+    view_proj: [[f32; 4]; 4],
+    inv_view_proj: [[f32; 4]; 4],
+    eye: [f32; 4],
+    max_distance: f32, // max_distance: world units marched along the reflected ray
+    thickness: f32,    // thickness: how far behind the depth buffer a sample still counts as a hit
+    steps: u32,
+    _pad: u32,
+}
+
+// Screen-space reflections: the frame is drawn into `scene_color`, then a fullscreen
+// pass ray-marches the depth buffer from every reflective pixel and composites the
+// result into the surface.
+pub struct ScreenSpaceReflections {
+    // Off until toggled with R; the depth-stencil is only sampled, and bound here, while on
+    pub enabled: bool,
+    pub scene_color: Texture,
+    pub normals: NormalTarget,
+    uniform: SsrUniform,
+    buffer: wgpu::Buffer,
+    pub bind_group: Option<wgpu::BindGroup>,
+}
+
+impl ScreenSpaceReflections {
+    pub fn new(
+        device: &wgpu::Device,
+        config: &wgpu::SurfaceConfiguration,
+        sample_count: u32,
+    ) -> Self {
+        let uniform = SsrUniform {
+            view_proj: cgmath::Matrix4::identity().into(),
+            inv_view_proj: cgmath::Matrix4::identity().into(),
+            eye: [0.0; 4],
+            max_distance: 12.0,
+            thickness: 0.5,
+            steps: 64,
+            _pad: 0,
+        };
+        let buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("SSR Buffer"),
+            contents: bytemuck::cast_slice(&[uniform]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+
+        let scene_color = Texture::create_render_target(device, config, "scene_color");
+        let normals = NormalTarget::new(device, config, sample_count);
+
+        Self {
+            enabled: false,
+            scene_color,
+            normals,
+            uniform,
+            buffer,
+            bind_group: None,
+        }
+    }
+
+    // The targets follow the surface size; `depth_stencil` must be the new one too, and
+    // sampled when enabled.
+    pub fn resize(
+        &mut self,
+        device: &wgpu::Device,
+        config: &wgpu::SurfaceConfiguration,
+        depth_stencil: &StencilTexture,
+        sample_count: u32,
+    ) {
+        self.scene_color = Texture::create_render_target(device, config, "scene_color");
+        self.normals = NormalTarget::new(device, config, sample_count);
+        self.bind_group = self.enabled.then(|| {
+            Self::create_bind_group(
+                device,
+                &self.scene_color,
+                &self.normals,
+                depth_stencil,
+                &self.buffer,
+                sample_count,
+            )
+        });
+    }
+
+    pub fn update(&mut self, queue: &wgpu::Queue, camera: &Camera) {
+        let view_proj = camera.build_proj_only_matrix() * camera.build_view_only_matrix();
+        self.uniform.view_proj = view_proj.into();
+        self.uniform.inv_view_proj = view_proj
+            .invert()
+            .unwrap_or(cgmath::Matrix4::identity())
+            .into();
+        self.uniform.eye = camera.eye.to_homogeneous().into();
+        queue.write_buffer(&self.buffer, 0, bytemuck::cast_slice(&[self.uniform]));
+    }
+
+    pub fn bind_group_layout(device: &wgpu::Device, sample_count: u32) -> wgpu::BindGroupLayout {
+        device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("ssr_bind_group_layout"),
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        multisampled: false,
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        multisampled: sample_count > 1,
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        sample_type: wgpu::TextureSampleType::Float { filterable: false },
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 3,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        multisampled: false,
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        sample_type: wgpu::TextureSampleType::Float { filterable: false },
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 4,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+            ],
+        })
+    }
+
+    fn create_bind_group(
+        device: &wgpu::Device,
+        scene_color: &Texture,
+        normals: &NormalTarget,
+        depth_stencil: &StencilTexture,
+        buffer: &wgpu::Buffer,
+        sample_count: u32,
+    ) -> wgpu::BindGroup {
+        device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("ssr_bind_group"),
+            layout: &Self::bind_group_layout(device, sample_count),
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(&scene_color.view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::Sampler(&scene_color.sampler),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: wgpu::BindingResource::TextureView(&depth_stencil.depth_view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: wgpu::BindingResource::TextureView(&normals.view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 4,
+                    resource: buffer.as_entire_binding(),
+                },
+            ],
+        })
+    }
+}
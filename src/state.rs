@@ -1,38 +1,106 @@
-use std::sync::Arc;
+use std::{collections::BTreeMap, rc::Rc, sync::Arc};
 
-use cgmath::Vector3;
 use instant::Instant;
 use winit::{event_loop::ActiveEventLoop, keyboard::KeyCode, window::Window};
 
 use crate::{
     camera::{
-        bind_group_for_camera_uniform, create_camera_buffer, create_camera_reflected_buffer,
-        Camera, CameraController, CameraUniform,
+        bind_group_for_camera_uniform, create_camera_buffer, Camera, CameraController,
+        CameraUniform,
     },
     depth_stencil::{self, StencilTexture},
-    extra::{MirrorPlaneUniform, Spin, SpinUniform},
+    environment::ReflectiveModel,
+    extra::{Spin, SpinUniform, SurfaceUniform},
+    mirror::{
+        build_reflection_tree, Mirror, MirrorClipping, MirrorDesc, MirrorMaterial, MirrorShape,
+        MirrorTechnique, ReducedReflection, ReflectedCameraPool, ReflectionNode, ReflectionQuality,
+        ReflectionTexture, MAX_REFLECTION_DEPTH,
+    },
     model::{DrawModel, Model},
-    pipeline::Pipeline,
+    msaa::{self, DEFAULT_SAMPLE_COUNT},
+    offscreen::{self, OffscreenDesc, OffscreenTarget},
+    pipeline::{BindGroupLayouts, ScenePipelines},
+    pipeline_desc::PipelineCache,
+    portal::Portal,
+    profiler::{Profiler, MAX_TIMED_PASSES},
+    render_graph::{ColorAttachment, GraphPass, RenderGraph, TransientDesc, TransientTargets},
     resources,
-    texture::{self, create_multisampled_view, Texture},
-    utils::build_reflection_matrix,
-    vertex::{
-        create_index_buffer, create_instance_buffer, create_vertex_buffer, Instance, INDICES,
-        VERTICES,
+    screenshot::Screenshots,
+    ssr::ScreenSpaceReflections,
+    stats_overlay::StatsOverlay,
+    surface::{
+        self, FrameTarget, SurfaceFormats, WindowSurface, DEFAULT_FRAME_LATENCY,
+        DEFAULT_PRESENT_MODE,
     },
+    texture::Texture,
+    vertex::{create_instance_buffer, Instance, LayeredInstances, RenderLayer},
+    visibility::{Frustum, OcclusionQueries},
+    water::Water,
+};
+
+#[cfg(not(target_arch = "wasm32"))]
+use crate::{
+    recording::{Recorder, RecordingDesc},
+    shader_watch::ShaderWatcher,
 };
 
+// / P A S S   N A M E S
+// / Each named once, for the pass and for the passes ordered after it
+const STENCIL_PASS: &str = "stencil pass";
+const STENCIL_DEBUG_PASS: &str = "Stencil Debug Pass";
+const REFLECTION_PASS: &str = "reflection pass";
+const REDUCED_STENCIL_PASS: &str = "reduced stencil pass";
+const REDUCED_STENCIL_DEBUG_PASS: &str = "reduced Stencil Debug Pass";
+const REDUCED_REFLECTION_PASS: &str = "reduced reflection pass";
+const REFLECTION_UPSAMPLE_PASS: &str = "reflection upsample pass";
+const REFLECTION_TEXTURE_PASS: &str = "reflection texture pass";
+const ENVIRONMENT_FACE_PASS: &str = "environment face pass";
+const WATER_TEXTURE_PASS: &str = "water texture pass";
+const SCENE_PASS: &str = "Render Total Scene Pass";
+const MIRROR_SURFACE_PASS: &str = "mirror surface Render Pass";
+const SSR_PASS: &str = "ssr pass";
+const SHADER_ERROR_PASS: &str = "shader error pass";
+const STATS_OVERLAY_PASS: &str = "stats overlay pass";
+
+// Every pass a frame may have, for the render graph to check names against
+const PASS_NAMES: &[&str] = &[
+    STENCIL_PASS,
+    STENCIL_DEBUG_PASS,
+    REFLECTION_PASS,
+    REDUCED_STENCIL_PASS,
+    REDUCED_STENCIL_DEBUG_PASS,
+    REDUCED_REFLECTION_PASS,
+    REFLECTION_UPSAMPLE_PASS,
+    REFLECTION_TEXTURE_PASS,
+    ENVIRONMENT_FACE_PASS,
+    WATER_TEXTURE_PASS,
+    SCENE_PASS,
+    MIRROR_SURFACE_PASS,
+    SSR_PASS,
+    SHADER_ERROR_PASS,
+    STATS_OVERLAY_PASS,
+];
+
 pub struct State {
-    pub surface: wgpu::Surface<'static>,
+    // A window's surface, or a texture for offscreen rendering
+    target: FrameTarget,
     pub device: wgpu::Device,
     pub queue: wgpu::Queue,
+    // In the render format, the one every target and pipeline is built for
     pub config: wgpu::SurfaceConfiguration,
-    pub is_surface_configured: bool,
-    pub render_pipeline: wgpu::RenderPipeline,
-    pub vertex_buffer: wgpu::Buffer,
-    pub num_vertices: u32,
-    pub index_buffer: wgpu::Buffer,
-    pub num_indices: u32,
+    pipeline_cache: PipelineCache,
+    // Kept to rebuild the pipelines, e.g. for the wireframe variant
+    layouts: BindGroupLayouts,
+    pipelines: ScenePipelines,
+    wireframe: bool,
+    // Native only: src/shaders is watched while this is set
+    #[cfg(not(target_arch = "wasm32"))]
+    shader_watcher: Option<ShaderWatcher>,
+    // Frames being written to a GIF or PNGs; the scene then steps by a fixed time
+    #[cfg(not(target_arch = "wasm32"))]
+    recorder: Option<Recorder>,
+    // Last compile error of each shader file that fails to reload
+    shader_errors: BTreeMap<String, String>,
     #[allow(dead_code)]
     pub diffuse_texture: Texture,
     pub diffuse_bind_group: wgpu::BindGroup,
@@ -45,8 +113,7 @@ pub struct State {
     camera_buffer: wgpu::Buffer,
     camera_bind_group: wgpu::BindGroup,
     camera_controller: CameraController,
-    instances: Vec<Instance>,
-    instance_buffer: wgpu::Buffer,
+    instances: LayeredInstances,
     //depth_texture: Texture,
     obj_model: Model,
     last_frame: Instant,
@@ -55,21 +122,37 @@ pub struct State {
     spin_buffer: wgpu::Buffer,
     spin_bind_group: wgpu::BindGroup,
     depth_stencil: StencilTexture,
-    stencil_pipeline: wgpu::RenderPipeline,
-    reflection_pipeline: wgpu::RenderPipeline,
-    mirror_instance: Instance,
-    mirror_instance_buffer: wgpu::Buffer,
-    mirror_plane_uniform: MirrorPlaneUniform,
-    mirror_plane_buffer: wgpu::Buffer,
-    mirror_plane_bind_group: wgpu::BindGroup,
-    //    camera_reflected_uniform: CameraUniform,
-    camera_reflected_buffer: wgpu::Buffer,
-    camera_reflected_bind_group: wgpu::BindGroup,
-    mirror_surface_pipeline: wgpu::RenderPipeline,
-    multisampled_framebuffer: Option<wgpu::TextureView>,
+    mirrors: Vec<Mirror>,
+    // Mirrors drawn this frame (in view and not occluded last frame)
+    mirror_visible: Vec<bool>,
+    occlusion: OcclusionQueries,
+    screenshots: Screenshots,
+    profiler: Profiler,
+    stats_overlay: StatsOverlay,
+    reflection_depth: u32,
+    mirror_clipping: MirrorClipping,
+    mirror_technique: MirrorTechnique,
+    reflection_textures: Vec<ReflectionTexture>,
+    reflection_quality: ReflectionQuality,
+    // Offscreen target of the stencil reflections, below `ReflectionQuality::Full`
+    reduced_reflection: Option<ReducedReflection>,
+    reflective_models: Vec<ReflectiveModel>,
+    water: Water,
+    floor: Model,
+    floor_instance_buffer: wgpu::Buffer,
+    floor_bind_group: wgpu::BindGroup,
+    floor_spin_bind_group: wgpu::BindGroup,
+    floor_surface_bind_group: wgpu::BindGroup,
+    scene_surface_bind_group: wgpu::BindGroup,
+    ssr: ScreenSpaceReflections,
+    reflected_cameras: ReflectedCameraPool,
+    // The multisampled framebuffer and other targets only the render graph sees
+    transients: TransientTargets,
     sample_count: u32,
-    debug_stencil_pipeline: wgpu::RenderPipeline, // DEBUG
-    pub window: Arc<Window>,
+    // What the adapter can multisample, for `set_sample_count`
+    sample_counts: Vec<u32>,
+    // Without it, SSR only runs without MSAA
+    ssr_multisampled: bool,
 }
 
 impl State {
@@ -107,11 +190,84 @@ impl State {
                 .ok_or(anyhow::anyhow!("No adapter found"))?
         };
 
+        let (device, queue) = Self::request_device(&adapter).await?;
+
+        let surface_caps = surface.get_capabilities(&adapter);
+        // Shader code in this tutorial assumes an sRGB target: a surface without an sRGB
+        // format is drawn to through an sRGB view of it.
+        let surface_formats = SurfaceFormats::negotiate(&surface_caps, &adapter);
+        log::info!("surface formats: {surface_formats:?}");
+        // COPY_SRC for screenshots, where the surface allows it
+        let usage = wgpu::TextureUsages::RENDER_ATTACHMENT
+            | (surface_caps.usages & wgpu::TextureUsages::COPY_SRC);
+        let config = wgpu::SurfaceConfiguration {
+            usage,
+            // Render format; `surface_formats` configures the surface
+            format: surface_formats.render,
+            width: size.width,
+            height: size.height,
+            present_mode: surface::present_mode_fallback(
+                &surface_caps.present_modes,
+                DEFAULT_PRESENT_MODE,
+            ),
+            alpha_mode: surface_caps.alpha_modes[0],
+            view_formats: vec![],
+            desired_maximum_frame_latency: DEFAULT_FRAME_LATENCY,
+        };
+        log::info!(
+            "present mode: {:?} (supported: {:?})",
+            config.present_mode,
+            surface_caps.present_modes
+        );
+
+        let target = FrameTarget::Window(WindowSurface::new(
+            window,
+            surface,
+            surface_formats,
+            surface_caps.present_modes,
+        ));
+        Self::with_target(&adapter, device, queue, config, target).await
+    }
+
+    // Renders into a texture of `desc.width` x `desc.height` in `desc.format`, without a
+    // window; `read_frame` reads back the last frame rendered.
+    pub async fn new_offscreen(desc: OffscreenDesc) -> anyhow::Result<Self> {
+        let instance = wgpu::Instance::new(&wgpu::InstanceDescriptor {
+            // GL too: llvmpipe is the software adapter of many CI machines
+            #[cfg(not(target_arch = "wasm32"))]
+            backends: wgpu::Backends::all(),
+            #[cfg(target_arch = "wasm32")]
+            backends: wgpu::Backends::GL,
+            ..Default::default()
+        });
+        let adapter = instance
+            .request_adapter(&wgpu::RequestAdapterOptions {
+                power_preference: wgpu::PowerPreference::default(),
+                compatible_surface: None,
+                force_fallback_adapter: desc.force_fallback_adapter,
+            })
+            .await?;
+        log::info!("offscreen adapter: {:?}", adapter.get_info());
+        let (device, queue) = Self::request_device(&adapter).await?;
+
+        let config = desc.config();
+        let target = FrameTarget::Offscreen(OffscreenTarget::new(&device, &config));
+        Self::with_target(&adapter, device, queue, config, target).await
+    }
+
+    async fn request_device(
+        adapter: &wgpu::Adapter,
+    ) -> anyhow::Result<(wgpu::Device, wgpu::Queue)> {
         // Device & Queue
-        let (device, queue) = adapter
+        Ok(adapter
             .request_device(&wgpu::DeviceDescriptor {
                 label: None,
-                required_features: wgpu::Features::empty(),
+                // Wireframe, the adapter's MSAA sample counts and pass timings, where the
+                // adapter has them
+                required_features: adapter.features()
+                    & (wgpu::Features::POLYGON_MODE_LINE
+                        | wgpu::Features::TEXTURE_ADAPTER_SPECIFIC_FORMAT_FEATURES
+                        | wgpu::Features::TIMESTAMP_QUERY),
                 experimental_features: wgpu::ExperimentalFeatures::disabled(),
                 // WebGL doesn't support all of wgpu's features, so if
                 // we're building for the web we'll have to disable some.
@@ -123,42 +279,26 @@ impl State {
                 memory_hints: Default::default(),
                 trace: wgpu::Trace::Off,
             })
-            .await?;
-
-        let surface_caps = surface.get_capabilities(&adapter);
-        // Shader code in this tutorial assumes an sRGB surface texture. Using a different
-        // one will result in all the colors coming out darker. If you want to support non
-        // sRGB surfaces, you'll need to account for that when drawing to the frame.
-        let surface_format = surface_caps
-            .formats
-            .iter()
-            .find(|f| f.is_srgb())
-            .copied()
-            .unwrap_or(surface_caps.formats[0]);
-        let config = wgpu::SurfaceConfiguration {
-            usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
-            format: surface_format,
-            width: size.width,
-            height: size.height,
-            present_mode: surface_caps.present_modes[0],
-            alpha_mode: surface_caps.alpha_modes[0],
-            view_formats: vec![],
-            desired_maximum_frame_latency: 2,
-        };
-
-        // Vertex
-        let vertex_buffer = create_vertex_buffer(&device)?;
-
-        let num_vertices = VERTICES.len() as u32;
-
-        // Indices
+            .await?)
+    }
 
-        let index_buffer = create_index_buffer(&device)?;
-        let num_indices = INDICES.len() as u32;
+    // The scene, for frames going to `target`, in `config.format`
+    async fn with_target(
+        adapter: &wgpu::Adapter,
+        device: wgpu::Device,
+        queue: wgpu::Queue,
+        config: wgpu::SurfaceConfiguration,
+        target: FrameTarget,
+    ) -> anyhow::Result<Self> {
+        // / M S A A
+        // / Not every adapter multisamples every target: fall back to fewer samples.
+        let sample_counts = msaa::supported_sample_counts(adapter, &device, config.format);
+        let sample_count = msaa::fallback_sample_count(&sample_counts, DEFAULT_SAMPLE_COUNT);
+        log::info!("MSAA: {sample_count}x (supported: {sample_counts:?})");
+        let ssr_multisampled = msaa::samples_multisampled_depth(adapter, &device);
 
         // Texture from Image
 
-        let sample_count: u32 = 4;
         //let url = "images/github-colored-logo.png";
         let url = "images/wgpu-logo.png";
         let diffuse_texture =
@@ -186,21 +326,142 @@ impl State {
         // / I N S T A N C E S
         // /
 
-        let instances = Instance::generate_instances();
-        let instance_data = instances.iter().map(Instance::to_raw).collect::<Vec<_>>();
-        let instance_buffer = create_instance_buffer(&device, &instance_data);
+        // / Plus a cube no mirror shows, and one only the mirrors show (behind the camera).
+        let mut instances = Instance::generate_instances();
+        instances.push(
+            Instance::generate_instance(0.0, 0.0, -6.0, 30.0).with_layer(RenderLayer::DirectOnly),
+        );
+        instances.push(
+            Instance::generate_instance(0.0, -1.0, 4.0, 0.0)
+                .with_layer(RenderLayer::ReflectionOnly),
+        );
+        let instances = LayeredInstances::new(&device, instances);
+
+        // / F L O O R
+        // / Glossy: reflects the scene through screen-space reflections.
+
+        let floor = resources::load_model(
+            "models/floor.obj",
+            &device,
+            &queue,
+            &diffuse_bind_group_layout,
+        )
+        .await?;
+        let floor_texture = crate::texture::Texture::get_texture_from_image(
+            &device,
+            &queue,
+            "images/floor-checker.png",
+        )
+        .await?;
+        let (_, floor_bind_group) = floor_texture.bind_group_for_texture(&device);
+        let floor_instance_buffer = create_instance_buffer(
+            &device,
+            &[Instance::generate_instance(0.0, -2.5, 0.0, 0.0).to_raw()],
+        );
+        let floor_surface_bind_group = SurfaceUniform::new(0.6).create_bind_group(&device);
+        let scene_surface_bind_group = SurfaceUniform::new(0.0).create_bind_group(&device);
+
+        // / R E F L E C T I V E   M O D E L S
+        // / Any OBJ can be drawn as chrome, reflecting a cube map captured at its origin.
+
+        let sphere = resources::load_model(
+            "models/sphere.obj",
+            &device,
+            &queue,
+            &diffuse_bind_group_layout,
+        )
+        .await?;
+        let reflective_models = vec![ReflectiveModel::new(
+            &device,
+            &config,
+            Rc::new(sphere),
+            Instance::generate_instance(0.0, 3.0, -1.5, 0.0),
+            1.2,
+        )];
+
+        // / W A T E R
+        // / A pool in front of the cubes, a little above the floor.
+        let water = Water::new(
+            &device,
+            &queue,
+            &config,
+            "models/water.obj",
+            Instance::generate_instance(0.0, -2.2, -8.0, 0.0),
+            &diffuse_bind_group_layout,
+        )
+        .await?;
+
+        // / M I R R O R S
+        // / Each mirror writes its own stencil value (1, 2, ...) in the mask pass.
+        // / Shapes are planar meshes under res/models: pentagon, oval, arch, rectangle.
 
-        let mirror_instance = Instance::generate_instance(5.0, 1.0, 2.0, 45.0);
-        let mirror_instance_data = mirror_instance.to_raw_with_scale(1.5);
-        let mirror_instance_buffer = create_instance_buffer(&device, &[mirror_instance_data]);
+        let pentagon = MirrorShape::load(
+            "models/mirror-pentagon.obj",
+            &device,
+            &queue,
+            &diffuse_bind_group_layout,
+        )
+        .await?;
+        let oval = MirrorShape::load(
+            "models/mirror-oval.obj",
+            &device,
+            &queue,
+            &diffuse_bind_group_layout,
+        )
+        .await?;
+        let arch = MirrorShape::load(
+            "models/mirror-arch.obj",
+            &device,
+            &queue,
+            &diffuse_bind_group_layout,
+        )
+        .await?;
 
-        // / M I R R O R  P L A N E  U N I F O R M
+        // / Materials: a worn bathroom mirror and a clean, slightly brushed brass one.
+        let dirt = Rc::new(
+            Texture::get_texture_from_image(&device, &queue, "images/mirror-dirt.png").await?,
+        );
+        let scratches = Rc::new(
+            Texture::get_texture_from_image(&device, &queue, "images/mirror-scratches.png").await?,
+        );
 
-        let mirror_plane_uniform =
-            MirrorPlaneUniform::new(&mirror_instance.transform(), Vector3::unit_z());
-        let mirror_plane_buffer = mirror_plane_uniform.mirror_plane_buffer(&device);
-        let (mirror_plane_bind_group_layout, mirror_plane_bind_group) =
-            MirrorPlaneUniform::create_bind_group_layout(&device, &mirror_plane_buffer);
+        let mirrors = Mirror::build_mirrors(
+            &device,
+            &queue,
+            vec![
+                MirrorDesc {
+                    shape: pentagon,
+                    instance: Instance::generate_instance(5.0, 1.0, 2.0, 45.0),
+                    scale: 1.5,
+                    material: MirrorMaterial {
+                        dirt: Some((dirt, 0.6)),
+                        scratches: Some((scratches, 0.5)),
+                        ..Default::default()
+                    },
+                    exit: None,
+                },
+                MirrorDesc {
+                    shape: oval,
+                    instance: Instance::generate_instance(-5.0, 1.0, 2.0, -45.0),
+                    scale: 1.0,
+                    material: MirrorMaterial {
+                        tint: [1.0, 0.86, 0.62],
+                        reflectance: 0.75,
+                        roughness: 0.35,
+                        ..Default::default()
+                    },
+                    exit: None,
+                },
+                // A doorway behind the cubes that opens onto the far left of the floor
+                Portal::new(
+                    Instance::generate_instance(3.0, -1.0, -3.0, 0.0),
+                    Instance::generate_instance(-12.0, -1.0, -2.0, 90.0),
+                )
+                .into_desc(arch, 0.3),
+            ],
+        )?;
+        let mirror_clipping = MirrorClipping::for_adapter(adapter);
+        log::info!("mirror clipping: {:?}", mirror_clipping);
 
         // / C A M E R A
         // /
@@ -227,13 +488,6 @@ impl State {
 
         let camera_controller = CameraController::new(0.1);
 
-        let camera_reflected_uniform = CameraUniform::new();
-        //camera_reflected_uniform.update_view_proj(&camera);
-        let camera_reflected_buffer =
-            create_camera_reflected_buffer(&camera_reflected_uniform, &device);
-        let (camera_reflected_bind_group_layout, camera_reflected_bind_group) =
-            bind_group_for_camera_uniform(&camera_reflected_buffer, &device);
-
         // / D E P T H   T E X T U R E
         // /
 
@@ -242,11 +496,13 @@ impl State {
         // / S T E N C I L  T E X T U R E
         // /
 
+        // Not sampled until screen-space reflections are turned on
         let depth_stencil = depth_stencil::StencilTexture::create_stencil_texture(
             &device,
             &config,
             "depth_stencil",
             sample_count,
+            false,
         );
 
         // / S P I N
@@ -258,85 +514,57 @@ impl State {
         let (spin_bind_group_layout, spin_bind_group) =
             SpinUniform::bind_group_for_spin_uniform(&spin_buffer, &device);
 
-        // /
-        // / MultiSample Framebuffer
-
-        let multisampled_framebuffer: Option<wgpu::TextureView> = if sample_count > 1 {
-            Some(texture::create_multisampled_view(
-                &device,
-                &config,
-                sample_count,
-            ))
-        } else {
-            None
-        };
+        // The floor does not spin
+        let floor_spin_buffer = SpinUniform::new().create_spin_uniform_buffer(&device);
+        let (_, floor_spin_bind_group) =
+            SpinUniform::bind_group_for_spin_uniform(&floor_spin_buffer, &device);
 
         // / P I P E L I N E S
         // /
 
-        // Pipeline
-        let pipeline_struct = Pipeline::build_render_pipeline(
+        let layouts = BindGroupLayouts::new(
             &device,
-            &config,
-            sample_count,
             &diffuse_bind_group_layout,
             &camera_bind_group_layout,
             &spin_bind_group_layout,
-            &mirror_plane_bind_group_layout,
-        )?;
-        let render_pipeline = pipeline_struct.pipeline;
-
-        // Stencil Pipeline
-        let stencil_pipeline_struct =
-            Pipeline::mask_render_pipeline(&device, &camera_bind_group_layout, sample_count)?;
-
-        let stencil_pipeline = stencil_pipeline_struct.pipeline;
-
-        // Reflection Pipeline
-
-        let reflection_pipeline_struct = Pipeline::reflection_render_pipeline(
-            &device,
-            &config,
-            &diffuse_bind_group_layout,
-            &camera_reflected_bind_group_layout,
-            &spin_bind_group_layout,
-            &mirror_plane_bind_group_layout,
             sample_count,
-        )?;
-
-        let reflection_pipeline = reflection_pipeline_struct.pipeline;
-
-        // Mirror surface pipeline
-
-        let mirror_surface_pipeline_struct = Pipeline::mirror_surface_render_pipeline(
+        );
+        let mut pipeline_cache = PipelineCache::default();
+        let wireframe = false;
+        let pipelines = ScenePipelines::new(
+            &mut pipeline_cache,
             &device,
             &config,
-            &camera_bind_group_layout,
+            &layouts,
             sample_count,
-        )?;
+            wireframe,
+        );
 
-        let mirror_surface_pipeline = mirror_surface_pipeline_struct.pipeline;
+        // Occlusion culling of mirrors
+        let occlusion = OcclusionQueries::new(&device, mirrors.len());
 
-        // ================================
-        // /  D E B U G G I N G
-        use crate::debugger::Pipeline as DebugPipeline;
-        let debug_stencil_pipeline_struct =
-            DebugPipeline::debug_render_pipeline(&device, &config, sample_count)?;
-        let debug_stencil_pipeline = debug_stencil_pipeline_struct.pipeline;
+        // Frame stats
+        let profiler = Profiler::new(&device, &queue);
+        let stats_overlay = StatsOverlay::new(&device);
 
-        // ================================
+        // Screen-space reflections
+        let ssr = ScreenSpaceReflections::new(&device, &config, sample_count);
 
         Ok(Self {
-            surface,
+            #[cfg(not(target_arch = "wasm32"))]
+            shader_watcher: (cfg!(debug_assertions) && target.window().is_some())
+                .then(ShaderWatcher::new),
+            #[cfg(not(target_arch = "wasm32"))]
+            recorder: None,
+            target,
             device,
             queue,
             config,
-            is_surface_configured: false,
-            render_pipeline,
-            vertex_buffer,
-            num_vertices,
-            num_indices,
-            index_buffer,
+            pipeline_cache,
+            layouts,
+            pipelines,
+            wireframe,
+            shader_errors: BTreeMap::new(),
             diffuse_texture,
             diffuse_bind_group,
             another_texture,
@@ -348,7 +576,6 @@ impl State {
             camera_bind_group,
             camera_controller,
             instances,
-            instance_buffer,
             //   depth_texture,
             obj_model,
             last_frame,
@@ -357,21 +584,32 @@ impl State {
             spin_bind_group,
             spin,
             depth_stencil,
-            stencil_pipeline,
-            reflection_pipeline,
-            mirror_instance,
-            mirror_instance_buffer,
-            mirror_plane_uniform,
-            mirror_plane_buffer,
-            mirror_plane_bind_group,
-            //            camera_reflected_uniform,
-            camera_reflected_bind_group,
-            camera_reflected_buffer,
-            mirror_surface_pipeline,
-            multisampled_framebuffer,
+            mirror_visible: vec![true; mirrors.len()],
+            occlusion,
+            screenshots: Screenshots::default(),
+            profiler,
+            stats_overlay,
+            mirrors,
+            reflection_depth: 1,
+            mirror_clipping,
+            mirror_technique: MirrorTechnique::Stencil,
+            reflection_textures: Vec::new(),
+            reflection_quality: ReflectionQuality::Full,
+            reduced_reflection: None,
+            reflective_models,
+            water,
+            floor,
+            floor_instance_buffer,
+            floor_bind_group,
+            floor_spin_bind_group,
+            floor_surface_bind_group,
+            scene_surface_bind_group,
+            ssr,
+            reflected_cameras: ReflectedCameraPool::default(),
+            transients: TransientTargets::default(),
             sample_count,
-            debug_stencil_pipeline, // DEBUG
-            window,
+            sample_counts,
+            ssr_multisampled,
         })
     }
 
@@ -379,30 +617,192 @@ impl State {
         if width > 0 && height > 0 {
             self.config.width = width;
             self.config.height = height;
-            self.surface.configure(&self.device, &self.config);
-            self.is_surface_configured = true;
+            self.target.resize(&self.device, &self.config);
             self.camera.aspect = width as f32 / height as f32;
 
             // This is a fix from chatgpt otherwise it only works for desktop not for browser.
             self.camera_uniform.update_view_proj(&self.camera);
-            //self.depth_texture = texture::Texture::create_depth_texture(&self.device, &self.config, "depth_texture");
-            self.depth_stencil = depth_stencil::StencilTexture::create_stencil_texture(
-                &self.device,
-                &self.config,
-                "depth_stencil",
-                self.sample_count,
+            self.create_render_targets();
+        }
+    }
+
+    pub fn window(&self) -> Option<&Arc<Window>> {
+        self.target.window()
+    }
+
+    // The last frame rendered by a `new_offscreen` state, as RGBA.
+    pub async fn read_frame(&self) -> anyhow::Result<image::RgbaImage> {
+        match &self.target {
+            FrameTarget::Offscreen(offscreen) => {
+                offscreen::read_rgba(&self.device, &self.queue, &offscreen.texture).await
+            }
+            FrameTarget::Window(_) => anyhow::bail!("read_frame: offscreen states only"),
+        }
+    }
+
+    // Per-pass GPU times (with TIMESTAMP_QUERY) and the CPU encode time, averaged, in an
+    // overlay and the log.
+    pub fn set_stats_enabled(&mut self, enabled: bool) {
+        self.profiler.set_enabled(enabled);
+        log::info!("frame stats: {enabled}");
+    }
+
+    pub fn toggle_stats(&mut self) {
+        self.set_stats_enabled(!self.profiler.enabled());
+    }
+
+    // Screen-space reflections over the finished frame. The depth-stencil is recreated,
+    // as it can only be sampled when created for it.
+    pub fn set_ssr_enabled(&mut self, enabled: bool) {
+        if enabled == self.ssr.enabled {
+            return;
+        }
+        if enabled && !self.ssr_supported(self.sample_count) {
+            log::warn!(
+                "screen-space reflections: the adapter can't sample a multisampled depth, \
+                 turn MSAA off first"
             );
+            return;
+        }
+        self.ssr.enabled = enabled;
+        self.create_depth_stencil();
+        log::info!("screen-space reflections: {enabled}");
+    }
 
-            if self.multisampled_framebuffer.is_some() {
-                self.multisampled_framebuffer = Some(create_multisampled_view(
-                    &self.device,
-                    &self.config,
-                    self.sample_count,
-                ));
-            };
+    pub fn toggle_ssr(&mut self) {
+        self.set_ssr_enabled(!self.ssr.enabled);
+    }
+
+    fn ssr_supported(&self, sample_count: u32) -> bool {
+        sample_count == 1 || self.ssr_multisampled
+    }
+
+    // The stats readout, one line per measurement, as the overlay shows it.
+    pub fn frame_stats(&self) -> Vec<String> {
+        self.profiler.lines()
+    }
+
+    // Saves the next frame as a timestamped PNG: in the working directory, or as a download
+    // on the web.
+    pub fn capture_frame(&mut self) {
+        self.screenshots.request();
+    }
+
+    // Records the next frames, one `update` + `render` each, until `desc.length` is reached
+    // or `stop_recording`. Replaces a recording in progress.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn start_recording(&mut self, desc: RecordingDesc) -> anyhow::Result<()> {
+        self.stop_recording();
+        self.recorder = Some(Recorder::new(desc)?);
+        Ok(())
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn stop_recording(&mut self) {
+        if let Some(recorder) = self.recorder.take() {
+            recorder.stop();
+        }
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn is_recording(&self) -> bool {
+        self.recorder.is_some()
+    }
+
+    // A 4 second GIF, or stops the one being recorded.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn toggle_recording(&mut self) {
+        if self.is_recording() {
+            self.stop_recording();
+        } else if let Err(e) = self.start_recording(RecordingDesc::gif(4.0)) {
+            log::error!("recording: {e:#}");
         }
     }
 
+    #[cfg(not(target_arch = "wasm32"))]
+    fn finish_recorded_frame(&mut self) {
+        let Some(recorder) = &mut self.recorder else {
+            return;
+        };
+        if let Err(e) = recorder.finish(&self.device) {
+            log::error!("recording: {e:#}");
+            self.stop_recording();
+        } else if recorder.is_done() {
+            self.stop_recording();
+        }
+    }
+
+    // Fifo (vsync), Mailbox or Immediate; an unsupported mode falls back to the closest.
+    pub fn set_present_mode(&mut self, requested: wgpu::PresentMode) {
+        match &mut self.target {
+            FrameTarget::Window(window_surface) => {
+                window_surface.set_present_mode(&self.device, &mut self.config, requested)
+            }
+            FrameTarget::Offscreen(_) => log::warn!("present mode: no surface offscreen"),
+        }
+    }
+
+    pub fn cycle_present_mode(&mut self) {
+        self.set_present_mode(match self.config.present_mode {
+            wgpu::PresentMode::Fifo => wgpu::PresentMode::Mailbox,
+            wgpu::PresentMode::Mailbox => wgpu::PresentMode::Immediate,
+            _ => wgpu::PresentMode::Fifo,
+        });
+    }
+
+    // Vsync to save power, off for uncapped frame rates (benchmarks).
+    pub fn toggle_vsync(&mut self) {
+        match &mut self.target {
+            FrameTarget::Window(window_surface) => {
+                window_surface.toggle_vsync(&self.device, &mut self.config)
+            }
+            FrameTarget::Offscreen(_) => log::warn!("vsync: no surface offscreen"),
+        }
+    }
+
+    // Frames queued ahead of the display: 1 for the least input lag, more for smoother
+    // frame pacing.
+    pub fn set_frame_latency(&mut self, frames: u32) {
+        match &mut self.target {
+            FrameTarget::Window(window_surface) => {
+                window_surface.set_frame_latency(&self.device, &mut self.config, frames)
+            }
+            FrameTarget::Offscreen(_) => log::warn!("frame latency: no surface offscreen"),
+        }
+    }
+
+    // Everything sized after the surface or multisampled like it.
+    fn create_render_targets(&mut self) {
+        //self.depth_texture = texture::Texture::create_depth_texture(&self.device, &self.config, "depth_texture");
+        self.create_depth_stencil();
+        self.water.resize(
+            &self.device,
+            &self.reflection_quality.scaled_config(&self.config),
+        );
+        self.create_reduced_reflection();
+
+        if self.mirror_technique == MirrorTechnique::RenderTexture {
+            self.create_reflection_textures();
+        }
+    }
+
+    // The depth-stencil, bindable as a texture while SSR samples it, and the SSR targets.
+    fn create_depth_stencil(&mut self) {
+        self.depth_stencil = depth_stencil::StencilTexture::create_stencil_texture(
+            &self.device,
+            &self.config,
+            "depth_stencil",
+            self.sample_count,
+            self.ssr.enabled,
+        );
+        self.ssr.resize(
+            &self.device,
+            &self.config,
+            &self.depth_stencil,
+            self.sample_count,
+        );
+    }
+
     pub fn update(&mut self) {
         // Delta time
         let now = Instant::now();
@@ -412,8 +812,15 @@ impl State {
         // Clamp for browser tab resume
         dt = dt.min(0.1);
 
+        // A recording steps by its frame time, however long the frames take to encode
+        #[cfg(not(target_arch = "wasm32"))]
+        if let Some(recorder) = &self.recorder {
+            dt = recorder.time_step();
+        }
+
         // Update logic
         self.spin.update(dt);
+        self.water.animate(dt);
 
         // Update GPU data
         self.spin_uniform.update_from_angle(self.spin.angle());
@@ -426,24 +833,30 @@ impl State {
 
         // Camera
         self.camera_controller.update_camera(&mut self.camera);
+
+        #[cfg(not(target_arch = "wasm32"))]
+        self.reload_shaders();
     }
     pub fn render(&mut self) -> Result<(), wgpu::SurfaceError> {
-        self.window.request_redraw();
+        if let Some(window) = self.target.window() {
+            window.request_redraw();
+        }
 
         // We can't render unless the surface is configured
-        if !self.is_surface_configured {
+        let Some(output) = self.target.acquire()? else {
             return Ok(());
-        }
-
-        let output = self.surface.get_current_texture()?;
+        };
 
-        let view = output
-            .texture
-            .create_view(&wgpu::TextureViewDescriptor::default());
-            // .create_view(&wgpu::TextureViewDescriptor{
-            //     format: Some(wgpu::TextureFormat::Rgba8Unorm),  
-            //     ..Default::default()});
+        let surface_view = output.view.clone();
+        // With screen-space reflections the frame is drawn offscreen first
+        let view = if self.ssr.enabled {
+            self.ssr.scene_color.view.clone()
+        } else {
+            surface_view.clone()
+        };
 
+        let time_passes = self.profiler.begin_frame(&self.device);
+        let encode_start = Instant::now();
         let mut encoder = self
             .device
             .create_command_encoder(&wgpu::CommandEncoderDescriptor {
@@ -453,10 +866,9 @@ impl State {
         // Camera uniform normal mode (for non-reflected mode)
 
         self.camera_uniform.update_view_proj(&self.camera);
-
-        //
-        // S T E N C I L   P A S S
-        //
+        if self.ssr.enabled {
+            self.ssr.update(&self.queue, &self.camera);
+        }
 
         // Write Camera buffer
 
@@ -467,300 +879,1002 @@ impl State {
         );
         // /
 
-        let mut stencil_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
-            label: Some("stencil pass"),
-            color_attachments: &[],
-            depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
-                view: &self.depth_stencil.view,
-                depth_ops: None,
-                stencil_ops: Some(wgpu::Operations {
-                    load: wgpu::LoadOp::Clear(0),
-                    store: wgpu::StoreOp::Store,
-                }),
-            }),
-            occlusion_query_set: None,
-            timestamp_writes: None,
-        });
-        stencil_pass.set_stencil_reference(1);
-        stencil_pass.set_pipeline(&self.stencil_pipeline);
-        stencil_pass.set_bind_group(0, &self.camera_bind_group, &[]);
-        stencil_pass.set_vertex_buffer(0, self.vertex_buffer.slice(..));
-        stencil_pass.set_vertex_buffer(1, self.mirror_instance_buffer.slice(..));
-        stencil_pass.set_index_buffer(self.index_buffer.slice(..), wgpu::IndexFormat::Uint16);
-        //stencil_pass.draw_indexed(0..self.num_indices, 0, 0..1);
-        //stencil_pass.draw_indexed(0..self.num_indices, 0, 0..self.mirror_instance.len() as _);
-        stencil_pass.draw_indexed(0..self.num_indices, 0, 0..1);
-
-        drop(stencil_pass);
+        // / V I S I B I L I T Y
+        // / Mirrors off-screen or occluded last frame get no pass at all, mirrors seen from
+        // / behind only their surface.
+        let frustum = Frustum::from_view_proj(
+            self.camera.build_proj_only_matrix() * self.camera.build_view_only_matrix(),
+        );
+        let in_view: Vec<bool> = self
+            .mirrors
+            .iter()
+            .map(|mirror| mirror.in_view(&frustum))
+            .collect();
+        let query_occlusion = self.occlusion.begin_frame(&self.device);
+        self.mirror_visible = in_view
+            .iter()
+            .enumerate()
+            .map(|(i, &in_view)| in_view && self.occlusion.is_visible(i))
+            .collect();
 
-        // / =================================
-        // /    D E B U G G I N G
-        // /
+        // Write the reflected camera and plane of every mirror
+        for mirror in &mut self.mirrors {
+            mirror.update(&self.queue, &self.camera, self.mirror_clipping);
+        }
 
-        let render_pass_color_attachments = match &self.multisampled_framebuffer {
-            Some(texture_view) => wgpu::RenderPassColorAttachment {
-                view: texture_view,
-                depth_slice: None,
-                resolve_target: Some(&view),
-                ops: wgpu::Operations {
-                    load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
-                    store: wgpu::StoreOp::Store,
-                },
-            },
-            None => wgpu::RenderPassColorAttachment {
-                view: &view,
-                resolve_target: None,
-                depth_slice: None,
-                ops: wgpu::Operations {
-                    load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
-                    store: wgpu::StoreOp::Store,
-                },
-            },
+        // / F R A M E   D A T A
+        // / Everything the passes read, written before any of them is recorded.
+        let reflection_trees = match self.mirror_technique {
+            MirrorTechnique::Stencil => self.update_reflection_trees(),
+            MirrorTechnique::RenderTexture => Vec::new(),
         };
+        let environment_due = self.update_environment_maps();
+        self.water
+            .update(&self.queue, &self.camera, self.mirror_clipping);
 
-        let mut debug_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
-            label: Some("Stencil Debug Pass"),
-            color_attachments: &[Some(render_pass_color_attachments)],
-            depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
-                view: &self.depth_stencil.view,
-                depth_ops: None,
-                stencil_ops: Some(wgpu::Operations {
-                    load: wgpu::LoadOp::Load,
-                    store: wgpu::StoreOp::Store,
-                }),
-            }),
-            occlusion_query_set: None,
-            timestamp_writes: None,
-        });
+        //
+        // / R E N D E R   G R A P H
+        //
 
-        debug_pass.set_pipeline(&self.debug_stencil_pipeline);
-        debug_pass.set_stencil_reference(1);
-        debug_pass.draw(0..3, 0..1);
-        drop(debug_pass);
+        let mut graph = RenderGraph::new(PASS_NAMES);
+        match self.mirror_technique {
+            MirrorTechnique::Stencil => {
+                self.add_stencil_reflection_passes(&mut graph, &view, &reflection_trees)
+            }
+            MirrorTechnique::RenderTexture => self.add_texture_reflection_passes(&mut graph),
+        }
+        self.add_environment_map_passes(&mut graph, &environment_due);
+        self.add_water_passes(&mut graph);
+        self.add_scene_passes(&mut graph, &view, &in_view, query_occlusion);
+
+        // / S C R E E N - S P A C E   R E F L E C T I O N S
+        // / Offscreen frame + reflections -> surface
+        if let Some(bind_group) = &self.ssr.bind_group {
+            let pipeline = &self.pipelines.ssr;
+            graph.add(
+                GraphPass::new(SSR_PASS, move |ssr_pass| {
+                    ssr_pass.set_pipeline(pipeline);
+                    ssr_pass.set_bind_group(0, bind_group, &[]);
+                    ssr_pass.draw(0..3, 0..1);
+                })
+                .color(ColorAttachment::new(
+                    &surface_view,
+                    wgpu::LoadOp::Clear(wgpu::Color::BLACK),
+                ))
+                .after(&[MIRROR_SURFACE_PASS]),
+            );
+        }
 
-        // // // ============================
+        // / S H A D E R   E R R O R
+        // / Over whatever reached the surface
+        if !self.shader_errors.is_empty() {
+            graph.add(
+                GraphPass::new(SHADER_ERROR_PASS, |error_pass| {
+                    error_pass.set_pipeline(&self.pipelines.shader_error);
+                    error_pass.draw(0..3, 0..1);
+                })
+                .color(ColorAttachment::new(&surface_view, wgpu::LoadOp::Load))
+                .after(&[MIRROR_SURFACE_PASS, SSR_PASS]),
+            );
+        }
 
-        //
-        // /  R E F L E C T I O N   P A S S
-        //
+        // / S T A T S
+        // / Over everything else
+        if self.profiler.enabled() {
+            let scale = (self.config.height / 240).max(1);
+            self.stats_overlay
+                .set_text(&self.queue, &self.profiler.lines(), scale);
+            graph.add(
+                GraphPass::new(STATS_OVERLAY_PASS, |overlay_pass| {
+                    overlay_pass.set_pipeline(&self.pipelines.stats_overlay);
+                    overlay_pass.set_bind_group(0, &self.stats_overlay.bind_group, &[]);
+                    overlay_pass.draw(0..3, 0..1);
+                })
+                .color(ColorAttachment::new(&surface_view, wgpu::LoadOp::Load))
+                .after(&[MIRROR_SURFACE_PASS, SSR_PASS, SHADER_ERROR_PASS]),
+            );
+        }
+        if let Some(query_set) = self.profiler.query_set().filter(|_| time_passes) {
+            graph.time_passes(query_set, MAX_TIMED_PASSES);
+        }
 
-        //  I N   F L U X
+        let passes = match graph.execute(&self.device, &mut encoder, &self.transients) {
+            Ok(passes) => passes,
+            Err(e) => {
+                log::error!("render graph: {e:#}");
+                Vec::new()
+            }
+        };
+        if time_passes {
+            self.profiler.resolve(&mut encoder, passes);
+        }
 
-        // Write Camera buffer
-        // 1. Get mirror plane from CPU-side transform
+        if query_occlusion {
+            self.occlusion.resolve(&mut encoder, in_view);
+        }
 
-        let mirror_transform = Instance::transform(&self.mirror_instance);
+        self.screenshots
+            .record(&self.device, &mut encoder, &output.texture);
+        #[cfg(not(target_arch = "wasm32"))]
+        if let Some(recorder) = &mut self.recorder {
+            if let Err(e) = recorder.record(&self.device, &mut encoder, &output.texture) {
+                log::error!("recording: {e:#}");
+                self.stop_recording();
+            }
+        }
 
-        let reflection = build_reflection_matrix(&mirror_transform, Vector3::unit_z());
+        let commands = encoder.finish();
+        self.profiler.record_cpu_encode(encode_start.elapsed());
 
-        let reflected_camera: [[f32; 4]; 4] = self.camera.build_reflected_camera(reflection).into();
+        // submit will accept anything that implements IntoIter
+        self.queue.submit(std::iter::once(commands));
+        self.occlusion.map();
+        self.profiler.map();
+        self.screenshots.finish(&self.device);
+        #[cfg(not(target_arch = "wasm32"))]
+        self.finish_recorded_frame();
+        output.present();
 
-        self.queue.write_buffer(
-            &self.camera_reflected_buffer,
-            0,
-            bytemuck::cast_slice(&[reflected_camera]),
-        );
-        self.queue.write_buffer(
-            &self.mirror_plane_buffer,
-            0,
-            bytemuck::cast_slice(&[self.mirror_plane_uniform]),
-        );
-        // /
+        Ok(())
+    }
 
-        let depth_stencil_attachment = wgpu::RenderPassDepthStencilAttachment {
-            view: &self.depth_stencil.view,
+    // Multisampled stand-in for the framebuffer, when MSAA is on
+    fn framebuffer_msaa(&self) -> Option<TransientDesc> {
+        TransientDesc::multisampled("multisampled_framebuffer", &self.config, self.sample_count)
+    }
 
-            depth_ops: Some(wgpu::Operations {
-                load: wgpu::LoadOp::Clear(1.0), // <- clear depth
-                store: wgpu::StoreOp::Store,
-            }),
+    // Total scene, then the mirror surfaces (with their occlusion queries) on top.
+    fn add_scene_passes<'a>(
+        &'a self,
+        graph: &mut RenderGraph<'a>,
+        view: &'a wgpu::TextureView,
+        in_view: &'a [bool],
+        query_occlusion: bool,
+    ) {
+        //
+        // T O T A L  S C E N E
+        //
 
-            stencil_ops: Some(wgpu::Operations {
-                load: wgpu::LoadOp::Load, // <- keep mirror mask
-                store: wgpu::StoreOp::Store,
-            }),
+        // Keep the reflections drawn so far, unless they live in their own textures
+        let (color_load, depth_load) = match self.mirror_technique {
+            MirrorTechnique::Stencil => (wgpu::LoadOp::Load, wgpu::LoadOp::Load),
+            MirrorTechnique::RenderTexture => (
+                wgpu::LoadOp::Clear(wgpu::Color::BLACK),
+                wgpu::LoadOp::Clear(1.0),
+            ),
         };
 
-        let render_pass_color_attachments = match &self.multisampled_framebuffer {
-            Some(texture_view) => wgpu::RenderPassColorAttachment {
-                view: texture_view,
-                depth_slice: None,
-                resolve_target: Some(&view),
-                ops: wgpu::Operations {
-                    load: wgpu::LoadOp::Load,
+        graph.add(
+            GraphPass::new(SCENE_PASS, move |render_pass| {
+                let bind_group = if self.is_space_pressed {
+                    &self.another_bind_group
+                } else {
+                    &self.diffuse_bind_group
+                };
+
+                render_pass.set_pipeline(&self.pipelines.render);
+                render_pass.set_bind_group(0, bind_group, &[]);
+                render_pass.set_bind_group(1, &self.camera_bind_group, &[]);
+                render_pass.set_bind_group(2, &self.spin_bind_group, &[]);
+                render_pass.set_bind_group(3, &self.scene_surface_bind_group, &[]);
+                render_pass.set_vertex_buffer(1, self.instances.buffer.slice(..));
+                render_pass.draw_mesh_instanced(&self.obj_model.meshes[0], self.instances.direct());
+
+                render_pass.set_bind_group(3, &self.floor_surface_bind_group, &[]);
+                self.draw_floor(render_pass);
+
+                render_pass.set_pipeline(&self.pipelines.water);
+                render_pass.set_bind_group(0, &self.water.bind_group, &[]);
+                render_pass.set_bind_group(1, &self.camera_bind_group, &[]);
+                self.water.draw(render_pass);
+
+                render_pass.set_pipeline(&self.pipelines.environment);
+                render_pass.set_bind_group(1, &self.camera_bind_group, &[]);
+                for reflective in &self.reflective_models {
+                    render_pass.set_bind_group(0, &reflective.environment.bind_group, &[]);
+                    reflective.draw(render_pass);
+                }
+            })
+            .color(ColorAttachment::resolved(
+                view,
+                self.framebuffer_msaa(),
+                color_load,
+            ))
+            .color(self.ssr.normals.color_attachment())
+            .depth_stencil(wgpu::RenderPassDepthStencilAttachment {
+                view: &self.depth_stencil.view,
+                depth_ops: Some(wgpu::Operations {
+                    load: depth_load,
                     store: wgpu::StoreOp::Store,
-                },
-            },
-            None => wgpu::RenderPassColorAttachment {
-                view: &view,
-                depth_slice: None,
-                resolve_target: None,
-                ops: wgpu::Operations {
+                }),
+                stencil_ops: None,
+            })
+            .after(&[
+                REFLECTION_PASS,
+                REFLECTION_UPSAMPLE_PASS,
+                REFLECTION_TEXTURE_PASS,
+                ENVIRONMENT_FACE_PASS,
+                WATER_TEXTURE_PASS,
+            ]),
+        );
+
+        // / M I R R O R   S U R F A C E
+        // /
+        graph.add(
+            GraphPass::new(MIRROR_SURFACE_PASS, move |mirror_surface_render_pass| {
+                self.draw_mirror_surfaces(mirror_surface_render_pass, in_view, query_occlusion)
+            })
+            .color(ColorAttachment::resolved(
+                view,
+                self.framebuffer_msaa(),
+                wgpu::LoadOp::Load, // <- DO NOT CLEAR
+            ))
+            .depth_stencil(wgpu::RenderPassDepthStencilAttachment {
+                view: &self.depth_stencil.view,
+                depth_ops: Some(wgpu::Operations {
                     load: wgpu::LoadOp::Load,
                     store: wgpu::StoreOp::Store,
-                },
-            },
-        };
-        let mut reflection_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
-            label: Some("reflection pass"),
-            color_attachments: &[Some(render_pass_color_attachments)],
-            depth_stencil_attachment: Some(depth_stencil_attachment),
-            occlusion_query_set: None,
-            timestamp_writes: None,
-        });
+                }),
+                stencil_ops: None,
+            })
+            // Last pass to test against the whole depth buffer
+            .occlusion_queries(query_occlusion.then(|| self.occlusion.query_set()))
+            .after(&[SCENE_PASS]),
+        );
+    }
 
-        reflection_pass.set_pipeline(&self.reflection_pipeline);
-        reflection_pass.set_stencil_reference(1);
-        reflection_pass.set_bind_group(0, &self.diffuse_bind_group, &[]);
-        reflection_pass.set_bind_group(1, &self.camera_reflected_bind_group, &[]);
-        reflection_pass.set_bind_group(2, &self.spin_bind_group, &[]);
-        reflection_pass.set_bind_group(3, &self.mirror_plane_bind_group, &[]);
-        reflection_pass.set_vertex_buffer(1, self.instance_buffer.slice(..));
+    fn draw_mirror_surfaces<'a>(
+        &'a self,
+        mirror_surface_render_pass: &mut wgpu::RenderPass<'a>,
+        in_view: &[bool],
+        query_occlusion: bool,
+    ) {
+        match self.mirror_technique {
+            MirrorTechnique::Stencil => {
+                mirror_surface_render_pass.set_bind_group(0, &self.camera_bind_group, &[]);
+                for (i, mirror) in self.visible_mirrors() {
+                    if query_occlusion {
+                        mirror_surface_render_pass.begin_occlusion_query(i as u32);
+                    }
+                    mirror.draw_surface(
+                        mirror_surface_render_pass,
+                        &self.pipelines.mirror_surface,
+                        &self.pipelines.mirror_coat,
+                    );
+                    if query_occlusion {
+                        mirror_surface_render_pass.end_occlusion_query();
+                    }
+                }
+            }
+            MirrorTechnique::RenderTexture => {
+                mirror_surface_render_pass.set_pipeline(&self.pipelines.mirror_surface_textured);
+                mirror_surface_render_pass.set_bind_group(1, &self.camera_bind_group, &[]);
+                for (i, mirror) in self.visible_mirrors() {
+                    let target = &self.reflection_textures[i];
+                    if query_occlusion {
+                        mirror_surface_render_pass.begin_occlusion_query(i as u32);
+                    }
+                    mirror_surface_render_pass.set_bind_group(0, &target.bind_group, &[]);
+                    mirror_surface_render_pass.set_bind_group(2, &mirror.material_bind_group, &[]);
+                    mirror_surface_render_pass.set_bind_group(3, &mirror.plane_bind_group, &[]);
+                    mirror.draw(mirror_surface_render_pass);
+                    if query_occlusion {
+                        mirror_surface_render_pass.end_occlusion_query();
+                    }
+                }
+            }
+        }
 
-        reflection_pass
-            .draw_mesh_instanced(&self.obj_model.meshes[0], 0..self.instances.len() as u32);
+        // Mirrors in view but occluded last frame: probe whether they still are
+        if query_occlusion {
+            mirror_surface_render_pass.set_pipeline(&self.pipelines.occlusion_probe);
+            mirror_surface_render_pass.set_bind_group(0, &self.camera_bind_group, &[]);
+            for (i, mirror) in self.mirrors.iter().enumerate() {
+                if in_view[i] && !self.mirror_visible[i] {
+                    mirror_surface_render_pass.begin_occlusion_query(i as u32);
+                    mirror.draw(mirror_surface_render_pass);
+                    mirror_surface_render_pass.end_occlusion_query();
+                }
+            }
+        }
+    }
+
+    // Mirrors seen inside each reflecting mirror, down to `reflection_depth` bounces, with
+    // their reflected cameras written.
+    fn update_reflection_trees(&mut self) -> Vec<Vec<ReflectionNode>> {
+        let mut camera_slots = 0;
+        let reflection_trees = self
+            .reflecting_mirrors()
+            .map(|(i, mirror)| {
+                build_reflection_tree(
+                    &self.mirrors,
+                    &self.camera,
+                    i,
+                    mirror.reflection(),
+                    mirror.stencil_reference,
+                    self.reflection_depth - 1,
+                    &mut camera_slots,
+                )
+            })
+            .collect::<Vec<_>>();
+
+        self.reflected_cameras.resize(&self.device, camera_slots);
+        for node in reflection_trees.iter().flatten() {
+            self.reflected_cameras.write(
+                &self.queue,
+                &self.mirrors,
+                node,
+                &self.camera,
+                self.mirror_clipping,
+            );
+        }
+        reflection_trees
+    }
 
-        drop(reflection_pass);
+    // Stencil mask, stencil debug view and reflection passes (MirrorTechnique::Stencil),
+    // into the framebuffer or, below full quality, into the reduced target first.
+    fn add_stencil_reflection_passes<'a>(
+        &'a self,
+        graph: &mut RenderGraph<'a>,
+        view: &'a wgpu::TextureView,
+        reflection_trees: &'a [Vec<ReflectionNode>],
+    ) {
+        match &self.reduced_reflection {
+            None => self.add_stencil_target_passes(
+                graph,
+                self.framebuffer_msaa(),
+                view,
+                &self.depth_stencil.view,
+                reflection_trees,
+                [STENCIL_PASS, STENCIL_DEBUG_PASS, REFLECTION_PASS],
+            ),
+            Some(reduced) => {
+                let msaa = TransientDesc::multisampled(
+                    "reduced_reflection_msaa",
+                    &self.reflection_quality.scaled_config(&self.config),
+                    self.sample_count,
+                );
+                self.add_stencil_target_passes(
+                    graph,
+                    msaa,
+                    &reduced.color.view,
+                    &reduced.depth_stencil.view,
+                    reflection_trees,
+                    [
+                        REDUCED_STENCIL_PASS,
+                        REDUCED_STENCIL_DEBUG_PASS,
+                        REDUCED_REFLECTION_PASS,
+                    ],
+                );
+                self.add_reflection_upsample_passes(graph, view, reduced);
+            }
+        }
+    }
 
-        // /
-        // T O T A L  S C E N E
+    // Each reflecting mirror's stencil reference where the mirror is, 0 elsewhere.
+    fn stencil_mask_pass<'a>(
+        &'a self,
+        name: &'static str,
+        depth_stencil: &'a wgpu::TextureView,
+    ) -> GraphPass<'a> {
+        GraphPass::new(name, move |stencil_pass| {
+            stencil_pass.set_pipeline(&self.pipelines.stencil);
+            stencil_pass.set_bind_group(0, &self.camera_bind_group, &[]);
+            for (_, mirror) in self.reflecting_mirrors() {
+                stencil_pass.set_stencil_reference(mirror.stencil_reference);
+                mirror.draw(stencil_pass);
+            }
+        })
+        .depth_stencil(wgpu::RenderPassDepthStencilAttachment {
+            view: depth_stencil,
+            depth_ops: None,
+            stencil_ops: Some(wgpu::Operations {
+                load: wgpu::LoadOp::Clear(0),
+                store: wgpu::StoreOp::Store,
+            }),
+        })
+    }
+
+    // The mirrors turn into surfaces at their own depth for the rest of the frame
+    fn draw_mirror_depths<'a>(&'a self, pass: &mut wgpu::RenderPass<'a>) {
+        pass.set_pipeline(&self.pipelines.mirror_depth);
+        pass.set_bind_group(0, &self.camera_bind_group, &[]);
+        for (_, mirror) in self.reflecting_mirrors() {
+            pass.set_stencil_reference(mirror.stencil_reference);
+            mirror.draw(pass);
+        }
+    }
+
+    // `names`: the mask, debug and reflection passes.
+    fn add_stencil_target_passes<'a>(
+        &'a self,
+        graph: &mut RenderGraph<'a>,
+        msaa: Option<TransientDesc>,
+        view: &'a wgpu::TextureView,
+        depth_stencil: &'a wgpu::TextureView,
+        reflection_trees: &'a [Vec<ReflectionNode>],
+        [mask, debug, reflection]: [&'static str; 3],
+    ) {
+        //
+        // S T E N C I L   P A S S
+        //
+
+        graph.add(self.stencil_mask_pass(mask, depth_stencil));
+
+        // / =================================
+        // /    D E B U G G I N G
         // /
 
-        // Write Camera buffer
-        self.queue.write_buffer(
-            &self.camera_buffer,
-            0,
-            bytemuck::cast_slice(&[self.camera_uniform]),
-        );
-        self.queue.write_buffer(
-            &self.mirror_plane_buffer,
-            0,
-            bytemuck::cast_slice(&[self.mirror_plane_uniform]),
+        graph.add(
+            GraphPass::new(debug, move |debug_pass| {
+                debug_pass.set_pipeline(&self.pipelines.debug_stencil);
+                for (_, mirror) in self.reflecting_mirrors() {
+                    debug_pass.set_stencil_reference(mirror.stencil_reference);
+                    debug_pass.draw(0..3, 0..1);
+                }
+            })
+            .color(ColorAttachment::resolved(
+                view,
+                msaa,
+                wgpu::LoadOp::Clear(wgpu::Color::BLACK),
+            ))
+            .depth_stencil(wgpu::RenderPassDepthStencilAttachment {
+                view: depth_stencil,
+                depth_ops: None,
+                stencil_ops: Some(wgpu::Operations {
+                    load: wgpu::LoadOp::Load,
+                    store: wgpu::StoreOp::Store,
+                }),
+            })
+            .after(&[mask]),
         );
 
+        // // // ============================
+
         //
-        let render_pass_color_attachments = match &self.multisampled_framebuffer {
-            Some(texture_view) => {
-                wgpu::RenderPassColorAttachment {
-                    view: texture_view,
-                    depth_slice: None,
-                    resolve_target: Some(&view),
-                    ops: wgpu::Operations {
-                        load: wgpu::LoadOp::Load, // <- DO NOT CLEAR
-                        store: wgpu::StoreOp::Store,
-                    },
+        // /  R E F L E C T I O N   P A S S
+        //
+
+        graph.add(
+            GraphPass::new(reflection, move |reflection_pass| {
+                // Once per mirror, limited to that mirror's stencil value
+                for ((_, mirror), nested) in self.reflecting_mirrors().zip(reflection_trees) {
+                    self.draw_reflected_scene(
+                        reflection_pass,
+                        &mirror.camera_reflected_bind_group,
+                        mirror.clip_plane_bind_group(),
+                        mirror.stencil_reference,
+                        self.instances_through(mirror),
+                    );
+                    self.draw_nested_reflections(
+                        reflection_pass,
+                        &mirror.camera_reflected_bind_group,
+                        mirror.stencil_reference,
+                        nested,
+                    );
                 }
-            }
-            None => wgpu::RenderPassColorAttachment {
-                view: &view,
-                depth_slice: None,
-                resolve_target: None,
-                ops: wgpu::Operations {
-                    load: wgpu::LoadOp::Load, // <- DO NOT CLEAR
+
+                self.draw_mirror_depths(reflection_pass);
+            })
+            .color(ColorAttachment::resolved(view, msaa, wgpu::LoadOp::Load))
+            .depth_stencil(wgpu::RenderPassDepthStencilAttachment {
+                view: depth_stencil,
+
+                depth_ops: Some(wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(1.0), // <- clear depth
                     store: wgpu::StoreOp::Store,
-                },
-            },
-        };
+                }),
+
+                stencil_ops: Some(wgpu::Operations {
+                    load: wgpu::LoadOp::Load, // <- keep mirror mask
+                    store: wgpu::StoreOp::Store,
+                }),
+            })
+            .after(&[debug]),
+        );
+    }
+
+    // Reduced reflections stretched over the mirrors of the full-size framebuffer, which
+    // gets its own stencil mask and mirror depths.
+    fn add_reflection_upsample_passes<'a>(
+        &'a self,
+        graph: &mut RenderGraph<'a>,
+        view: &'a wgpu::TextureView,
+        reduced: &'a ReducedReflection,
+    ) {
+        graph.add(self.stencil_mask_pass(STENCIL_PASS, &self.depth_stencil.view));
+
+        graph.add(
+            GraphPass::new(REFLECTION_UPSAMPLE_PASS, move |upsample_pass| {
+                upsample_pass.set_pipeline(&self.pipelines.reflection_upsample);
+                upsample_pass.set_bind_group(0, &reduced.bind_group, &[]);
+                for (_, mirror) in self.reflecting_mirrors() {
+                    upsample_pass.set_stencil_reference(mirror.stencil_reference);
+                    upsample_pass.draw(0..3, 0..1);
+                }
 
-        let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
-            label: Some("Render Total Scene Pass"),
-            color_attachments: &[Some(render_pass_color_attachments)],
-            depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                self.draw_mirror_depths(upsample_pass);
+            })
+            .color(ColorAttachment::resolved(
+                view,
+                self.framebuffer_msaa(),
+                wgpu::LoadOp::Clear(wgpu::Color::BLACK),
+            ))
+            .depth_stencil(wgpu::RenderPassDepthStencilAttachment {
                 view: &self.depth_stencil.view,
                 depth_ops: Some(wgpu::Operations {
-                    load: wgpu::LoadOp::Load, // <- clear depth again
+                    load: wgpu::LoadOp::Clear(1.0),
                     store: wgpu::StoreOp::Store,
                 }),
-                // stencil_ops: Some(wgpu::Operations {
-                //     load: wgpu::LoadOp::Load,
-                //     store: wgpu::StoreOp::Store,
-                // }),
-                stencil_ops: None,
-            }),
-            occlusion_query_set: None,
-            timestamp_writes: None,
-        });
+                stencil_ops: Some(wgpu::Operations {
+                    load: wgpu::LoadOp::Load,
+                    store: wgpu::StoreOp::Store,
+                }),
+            })
+            .after(&[STENCIL_PASS, REDUCED_REFLECTION_PASS]),
+        );
+    }
 
+    // Reflective models whose cube map is re-rendered this frame, face cameras written.
+    fn update_environment_maps(&mut self) -> Vec<bool> {
+        let due: Vec<bool> = self
+            .reflective_models
+            .iter_mut()
+            .map(|reflective| reflective.environment.due())
+            .collect();
+
+        for (reflective, &due) in self.reflective_models.iter().zip(&due) {
+            if due {
+                reflective
+                    .environment
+                    .update(&self.queue, reflective.position());
+            }
+        }
+        due
+    }
+
+    // Six cube faces per reflective model, when its update interval is due
+    fn add_environment_map_passes<'a>(&'a self, graph: &mut RenderGraph<'a>, due: &[bool]) {
         let bind_group = if self.is_space_pressed {
             &self.another_bind_group
         } else {
             &self.diffuse_bind_group
         };
 
-        render_pass.set_pipeline(&self.render_pipeline);
-        render_pass.set_bind_group(0, bind_group, &[]);
-        render_pass.set_bind_group(1, &self.camera_bind_group, &[]);
-        render_pass.set_bind_group(2, &self.spin_bind_group, &[]);
-        render_pass.set_bind_group(3, &self.mirror_plane_bind_group, &[]);
-        render_pass.set_vertex_buffer(1, self.instance_buffer.slice(..));
-        //render_pass.set_index_buffer(self.index_buffer.slice(..), wgpu::IndexFormat::Uint16);
-        //render_pass.draw_indexed(0..self.num_indices, 0, 0..1);
-        //render_pass.draw_indexed(0..self.num_indices, 0, 0..self.instances.len() as _);
-
-        render_pass.draw_mesh_instanced(&self.obj_model.meshes[0], 0..self.instances.len() as u32);
-        // TODO MIRROR
-        drop(render_pass);
-
-        // / M I R R O R   S U R F A C E
-        // /
-        self.queue.write_buffer(
-            &self.camera_buffer,
-            0,
-            bytemuck::cast_slice(&[self.camera_uniform]),
-        );
+        for (reflective, &due) in self.reflective_models.iter().zip(due) {
+            if !due {
+                continue;
+            }
+            let environment = &reflective.environment;
+
+            for (face, face_view) in environment.face_views.iter().enumerate() {
+                graph.add(
+                    GraphPass::new(ENVIRONMENT_FACE_PASS, move |face_pass| {
+                        face_pass.set_pipeline(&self.pipelines.environment_face);
+                        face_pass.set_bind_group(0, bind_group, &[]);
+                        face_pass.set_bind_group(1, environment.face_camera(face), &[]);
+                        face_pass.set_bind_group(2, &self.spin_bind_group, &[]);
+                        face_pass.set_vertex_buffer(1, self.instances.buffer.slice(..));
+                        face_pass.draw_mesh_instanced(
+                            &self.obj_model.meshes[0],
+                            self.instances.reflected(),
+                        );
+                        self.draw_floor(face_pass);
+                    })
+                    .color(ColorAttachment::new(
+                        face_view,
+                        wgpu::LoadOp::Clear(wgpu::Color::BLACK),
+                    ))
+                    .depth_stencil(wgpu::RenderPassDepthStencilAttachment {
+                        view: &environment.depth_stencil.view,
+                        depth_ops: Some(wgpu::Operations {
+                            load: wgpu::LoadOp::Clear(1.0),
+                            store: wgpu::StoreOp::Discard,
+                        }),
+                        stencil_ops: None,
+                    }),
+                );
+            }
+        }
+    }
 
-        let render_pass_color_attachments = match &self.multisampled_framebuffer {
-            Some(texture_view) => wgpu::RenderPassColorAttachment {
-                view: texture_view,
-                depth_slice: None,
-                resolve_target: Some(&view),
-                ops: wgpu::Operations {
-                    load: wgpu::LoadOp::Load, // <- DO NOT CLEAR
-                    store: wgpu::StoreOp::Store,
-                },
-            },
-            None => wgpu::RenderPassColorAttachment {
-                view: &view,
-                depth_slice: None,
-                resolve_target: None,
-                ops: wgpu::Operations {
-                    load: wgpu::LoadOp::Load, // <- DO NOT CLEAR
-                    store: wgpu::StoreOp::Store,
-                },
-            },
-        };
+    // One offscreen pass per mirror (MirrorTechnique::RenderTexture)
+    fn add_texture_reflection_passes<'a>(&'a self, graph: &mut RenderGraph<'a>) {
+        for (i, mirror) in self.reflecting_mirrors() {
+            let target = &self.reflection_textures[i];
+            graph.add(
+                GraphPass::new(REFLECTION_TEXTURE_PASS, move |reflection_pass| {
+                    self.draw_clipped_scene(
+                        reflection_pass,
+                        &mirror.camera_reflected_bind_group,
+                        mirror.clip_plane_bind_group(),
+                        self.instances_through(mirror),
+                    );
+                })
+                .color(ColorAttachment::new(
+                    &target.color.view,
+                    wgpu::LoadOp::Clear(wgpu::Color::BLACK),
+                ))
+                .depth_stencil(wgpu::RenderPassDepthStencilAttachment {
+                    view: &target.depth_stencil.view,
+                    depth_ops: Some(wgpu::Operations {
+                        load: wgpu::LoadOp::Clear(1.0),
+                        store: wgpu::StoreOp::Discard,
+                    }),
+                    stencil_ops: None,
+                }),
+            );
+        }
+    }
 
-        let mut mirror_surface_render_pass =
-            encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
-                label: Some("mirror surface Render Pass"),
-                color_attachments: &[Some(render_pass_color_attachments)],
-                depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
-                    view: &self.depth_stencil.view,
+    // Reflection, then refraction of the water, each into its own texture
+    fn add_water_passes<'a>(&'a self, graph: &mut RenderGraph<'a>) {
+        let targets = [
+            (
+                &self.water.reflection,
+                &self.water.reflected_camera_bind_group,
+                &self.water.reflection_plane_bind_group,
+                self.instances.reflected(),
+            ),
+            (
+                &self.water.refraction,
+                &self.water.refracted_camera_bind_group,
+                // Seen straight through the surface
+                &self.water.refraction_plane_bind_group,
+                self.instances.direct(),
+            ),
+        ];
+
+        for (target, camera_bind_group, plane_bind_group, instances) in targets {
+            graph.add(
+                GraphPass::new(WATER_TEXTURE_PASS, move |water_pass| {
+                    self.draw_clipped_scene(
+                        water_pass,
+                        camera_bind_group,
+                        plane_bind_group,
+                        instances,
+                    );
+                })
+                .color(ColorAttachment::new(
+                    &target.color.view,
+                    wgpu::LoadOp::Clear(wgpu::Color::BLACK),
+                ))
+                .depth_stencil(wgpu::RenderPassDepthStencilAttachment {
+                    view: &target.depth_stencil.view,
                     depth_ops: Some(wgpu::Operations {
-                        load: wgpu::LoadOp::Load, // <- clear depth again
-                        store: wgpu::StoreOp::Store,
+                        load: wgpu::LoadOp::Clear(1.0),
+                        store: wgpu::StoreOp::Discard,
                     }),
                     stencil_ops: None,
                 }),
-                occlusion_query_set: None,
-                timestamp_writes: None,
-            });
+            );
+        }
+    }
 
-        mirror_surface_render_pass.set_pipeline(&self.mirror_surface_pipeline);
-        mirror_surface_render_pass.set_bind_group(0, &self.camera_bind_group, &[]);
-        mirror_surface_render_pass.set_vertex_buffer(0, self.vertex_buffer.slice(..));
-        mirror_surface_render_pass.set_vertex_buffer(1, self.mirror_instance_buffer.slice(..));
-        mirror_surface_render_pass
-            .set_index_buffer(self.index_buffer.slice(..), wgpu::IndexFormat::Uint16);
-        mirror_surface_render_pass.draw_indexed(0..self.num_indices, 0, 0..1);
+    // Cubes of the given layer range and floor into an offscreen target, cut at the plane
+    // (reflection_texture_pipeline).
+    fn draw_clipped_scene<'a>(
+        &'a self,
+        pass: &mut wgpu::RenderPass<'a>,
+        camera_bind_group: &'a wgpu::BindGroup,
+        plane_bind_group: &'a wgpu::BindGroup,
+        instances: std::ops::Range<u32>,
+    ) {
+        pass.set_pipeline(&self.pipelines.reflection_texture);
+        pass.set_bind_group(0, &self.diffuse_bind_group, &[]);
+        pass.set_bind_group(1, camera_bind_group, &[]);
+        pass.set_bind_group(2, &self.spin_bind_group, &[]);
+        pass.set_bind_group(3, plane_bind_group, &[]);
+        pass.set_vertex_buffer(1, self.instances.buffer.slice(..));
+        pass.draw_mesh_instanced(&self.obj_model.meshes[0], instances);
+        self.draw_floor(pass);
+    }
 
-        drop(mirror_surface_render_pass);
+    // Mirrors that get their passes this frame, with their index.
+    fn visible_mirrors(&self) -> impl Iterator<Item = (usize, &Mirror)> {
+        self.mirrors
+            .iter()
+            .enumerate()
+            .filter(|(i, _)| self.mirror_visible[*i])
+    }
 
-        // submit will accept anything that implements IntoIter
-        self.queue.submit(std::iter::once(encoder.finish()));
-        output.present();
+    // Visible mirrors turned to the eye: the ones that get reflection passes.
+    fn reflecting_mirrors(&self) -> impl Iterator<Item = (usize, &Mirror)> {
+        self.visible_mirrors()
+            .filter(|(_, mirror)| mirror.faces(self.camera.eye))
+    }
 
-        Ok(())
+    // A portal shows the scene as the camera sees it, a mirror its reflection.
+    fn instances_through(&self, mirror: &Mirror) -> std::ops::Range<u32> {
+        if mirror.is_portal() {
+            self.instances.direct()
+        } else {
+            self.instances.reflected()
+        }
+    }
+
+    fn draw_reflected_scene<'a>(
+        &'a self,
+        pass: &mut wgpu::RenderPass<'a>,
+        camera_bind_group: &wgpu::BindGroup,
+        mirror_plane_bind_group: &wgpu::BindGroup,
+        stencil_reference: u32,
+        instances: std::ops::Range<u32>,
+    ) {
+        pass.set_pipeline(&self.pipelines.reflection);
+        pass.set_stencil_reference(stencil_reference);
+        pass.set_bind_group(0, &self.diffuse_bind_group, &[]);
+        pass.set_bind_group(1, camera_bind_group, &[]);
+        pass.set_bind_group(2, &self.spin_bind_group, &[]);
+        pass.set_bind_group(3, mirror_plane_bind_group, &[]);
+        pass.set_vertex_buffer(1, self.instances.buffer.slice(..));
+        pass.draw_mesh_instanced(&self.obj_model.meshes[0], instances);
+        self.draw_floor(pass);
+    }
+
+    // Floor with its own texture and no spin, on top of the pipeline and camera already set.
+    fn draw_floor<'a>(&'a self, pass: &mut wgpu::RenderPass<'a>) {
+        pass.set_bind_group(0, &self.floor_bind_group, &[]);
+        pass.set_bind_group(2, &self.floor_spin_bind_group, &[]);
+        pass.set_vertex_buffer(1, self.floor_instance_buffer.slice(..));
+        pass.draw_mesh(&self.floor.meshes[0]);
+    }
+
+    // Depth first, so one stencil value per level is enough: each nested mirror
+    // increments its parent's value, and gives it back once its subtree is drawn.
+    fn draw_nested_reflections<'a>(
+        &'a self,
+        pass: &mut wgpu::RenderPass<'a>,
+        parent_camera_bind_group: &wgpu::BindGroup,
+        parent_stencil: u32,
+        nodes: &[ReflectionNode],
+    ) {
+        for node in nodes {
+            let mirror = &self.mirrors[node.mirror];
+            let camera_bind_group = self.reflected_cameras.bind_group(node.camera_slot);
+
+            // 1. Mask the nested mirror as seen through the parent
+            pass.set_pipeline(&self.pipelines.nested_stencil);
+            pass.set_stencil_reference(parent_stencil);
+            pass.set_bind_group(0, parent_camera_bind_group, &[]);
+            mirror.draw(pass);
+
+            // 2. Clear depth inside it
+            pass.set_pipeline(&self.pipelines.clear_depth);
+            pass.set_stencil_reference(node.stencil_reference);
+            pass.draw(0..3, 0..1);
+
+            // 3. Reflected scene, then deeper mirrors
+            self.draw_reflected_scene(
+                pass,
+                camera_bind_group,
+                self.reflected_cameras.plane_bind_group(node.camera_slot),
+                node.stencil_reference,
+                self.instances_through(mirror),
+            );
+            self.draw_nested_reflections(
+                pass,
+                camera_bind_group,
+                node.stencil_reference,
+                &node.children,
+            );
+
+            // 4. Shade, write the mirror depth and restore the parent's stencil value
+            pass.set_stencil_reference(node.stencil_reference);
+            pass.set_bind_group(0, parent_camera_bind_group, &[]);
+            mirror.draw_surface(
+                pass,
+                &self.pipelines.nested_surface,
+                &self.pipelines.nested_coat,
+            );
+        }
+    }
+
+    // Puts the camera at `eye`, looking at `target`, e.g. for a fixed view offscreen.
+    pub fn look_at(&mut self, eye: cgmath::Point3<f32>, target: cgmath::Point3<f32>) {
+        self.camera.eye = eye;
+        self.camera.target = target;
+    }
+
+    // Number of bounces for mirror-in-mirror reflections, 1 = no nesting.
+    pub fn set_reflection_depth(&mut self, depth: u32) {
+        self.reflection_depth = depth.clamp(1, MAX_REFLECTION_DEPTH);
+        log::info!("reflection depth: {}", self.reflection_depth);
+    }
+
+    pub fn set_mirror_technique(&mut self, technique: MirrorTechnique) {
+        self.mirror_technique = technique;
+        match technique {
+            MirrorTechnique::Stencil => self.reflection_textures.clear(),
+            MirrorTechnique::RenderTexture => self.create_reflection_textures(),
+        }
+        log::info!("mirror technique: {:?}", self.mirror_technique);
+    }
+
+    fn create_reflection_textures(&mut self) {
+        self.reflection_textures = self
+            .mirrors
+            .iter()
+            .map(|_| {
+                ReflectionTexture::new(
+                    &self.device,
+                    &self.reflection_quality.scaled_config(&self.config),
+                )
+            })
+            .collect();
+    }
+
+    pub fn set_reflection_quality(&mut self, quality: ReflectionQuality) {
+        self.reflection_quality = quality;
+        self.create_reduced_reflection();
+        if self.mirror_technique == MirrorTechnique::RenderTexture {
+            self.create_reflection_textures();
+        }
+        self.water.resize(
+            &self.device,
+            &self.reflection_quality.scaled_config(&self.config),
+        );
+        log::info!("reflection quality: {:?}", self.reflection_quality);
+    }
+
+    // 1, 2, 4 or 8 samples; an unsupported count falls back to the next lower one.
+    pub fn set_sample_count(&mut self, requested: u32) {
+        let sample_count = msaa::fallback_sample_count(&self.sample_counts, requested);
+        if sample_count != requested {
+            log::warn!("MSAA: {requested}x is not supported, using {sample_count}x");
+        }
+        if sample_count == self.sample_count {
+            return;
+        }
+        self.sample_count = sample_count;
+        if self.ssr.enabled && !self.ssr_supported(sample_count) {
+            // MSAA wins; the targets below are recreated without a sampled depth
+            self.ssr.enabled = false;
+            log::warn!(
+                "screen-space reflections: off, the adapter can't sample a multisampled depth"
+            );
+        }
+        // Only the SSR layout depends on it; the cache keys on layout handles, so keeping
+        // the others lets every pipeline that doesn't multisample come from the cache.
+        self.layouts.ssr = ScreenSpaceReflections::bind_group_layout(&self.device, sample_count);
+        self.rebuild_pipelines();
+        self.create_render_targets();
+        log::info!(
+            "MSAA: {sample_count}x ({} pipelines cached)",
+            self.pipeline_cache.len()
+        );
+    }
+
+    // The next supported sample count, back to 1 after the highest.
+    pub fn cycle_sample_count(&mut self) {
+        let next = self
+            .sample_counts
+            .iter()
+            .copied()
+            .find(|&count| count > self.sample_count)
+            .unwrap_or(1);
+        self.set_sample_count(next);
+    }
+
+    fn create_reduced_reflection(&mut self) {
+        self.reduced_reflection = (self.reflection_quality != ReflectionQuality::Full).then(|| {
+            ReducedReflection::new(
+                &self.device,
+                &self.config,
+                self.reflection_quality,
+                self.sample_count,
+            )
+        });
+    }
+
+    // Re-render the cube maps every 1, 2, 4 or 8 frames, or only once.
+    pub fn cycle_environment_update_interval(&mut self) {
+        for reflective in &mut self.reflective_models {
+            let environment = &mut reflective.environment;
+            environment.update_interval = match environment.update_interval {
+                0 => 1,
+                8 => 0,
+                interval => interval * 2,
+            };
+            log::info!(
+                "environment map update interval: {} frames",
+                environment.update_interval
+            );
+        }
+    }
+
+    // Switch between the oblique near plane and the fragment-discard fallback.
+    pub fn toggle_mirror_clipping(&mut self) {
+        self.mirror_clipping = match self.mirror_clipping {
+            MirrorClipping::Oblique => MirrorClipping::Discard,
+            MirrorClipping::Discard => MirrorClipping::Oblique,
+        };
+        log::info!("mirror clipping: {:?}", self.mirror_clipping);
+    }
+
+    // Scene geometry as lines. Each variant is built once, then comes from the cache.
+    pub fn toggle_wireframe(&mut self) {
+        if !self
+            .device
+            .features()
+            .contains(wgpu::Features::POLYGON_MODE_LINE)
+        {
+            log::warn!("wireframe: POLYGON_MODE_LINE is not supported by this adapter");
+            return;
+        }
+        self.wireframe = !self.wireframe;
+        self.rebuild_pipelines();
+        log::info!(
+            "wireframe: {} ({} pipelines cached)",
+            self.wireframe,
+            self.pipeline_cache.len()
+        );
+    }
+
+    pub fn toggle_shader_hot_reload(&mut self) {
+        #[cfg(not(target_arch = "wasm32"))]
+        {
+            self.shader_watcher = match self.shader_watcher {
+                Some(_) => None,
+                None => Some(ShaderWatcher::new()),
+            };
+            match &self.shader_watcher {
+                Some(watcher) => log::info!("shader hot reload: watching {:?}", watcher.dir()),
+                None => log::info!("shader hot reload: off"),
+            }
+        }
+        #[cfg(target_arch = "wasm32")]
+        log::warn!("shader hot reload: native builds only");
+    }
+
+    // Shader files saved since the last frame, imported ones included. A file that fails
+    // to preprocess or compile keeps the last good pipelines; its error goes to the log,
+    // the window title and the frame.
+    #[cfg(not(target_arch = "wasm32"))]
+    fn reload_shaders(&mut self) {
+        let Some(watcher) = &mut self.shader_watcher else {
+            return;
+        };
+        let changed = watcher.poll();
+        if changed.is_empty() {
+            return;
+        }
+
+        for (file, text) in changed {
+            match self.pipeline_cache.reload(&self.device, &file, text) {
+                Ok(count) => {
+                    log::info!("shader {file}: reloaded {count} pipelines");
+                    self.shader_errors.remove(&file);
+                }
+                Err(error) => {
+                    log::error!("shader {file}: {error:#}");
+                    self.shader_errors.insert(file, format!("{error:#}"));
+                }
+            }
+        }
+        self.rebuild_pipelines();
+
+        let status = self.shader_errors.iter().next().map(|(file, error)| {
+            // The innermost cause, without naga's source excerpt
+            let error = error
+                .lines()
+                .map(str::trim)
+                .rfind(|line| !line.is_empty() && !line.contains(['│', '┌']));
+            format!("{file}: {}", error.unwrap_or("shader error"))
+        });
+        self.target.set_status(status.as_deref());
+    }
+
+    fn rebuild_pipelines(&mut self) {
+        self.pipelines = ScenePipelines::new(
+            &mut self.pipeline_cache,
+            &self.device,
+            &self.config,
+            &self.layouts,
+            self.sample_count,
+            self.wireframe,
+        );
+        self.pipeline_cache.sweep();
     }
 
     pub fn handle_key(&mut self, event_loop: &ActiveEventLoop, code: KeyCode, is_pressed: bool) {
@@ -777,6 +1891,34 @@ impl State {
                 | KeyCode::ArrowRight,
                 is_pressed,
             ) => self.camera_controller.handle_key(code, is_pressed),
+            (KeyCode::KeyC, true) => self.toggle_mirror_clipping(),
+            (KeyCode::KeyL, true) => self.toggle_wireframe(),
+            (KeyCode::KeyM, true) => self.cycle_sample_count(),
+            (KeyCode::KeyP, true) => self.cycle_present_mode(),
+            (KeyCode::KeyV, true) => self.toggle_vsync(),
+            (KeyCode::KeyF, true) => {
+                self.set_frame_latency(self.config.desired_maximum_frame_latency % 3 + 1)
+            }
+            (KeyCode::KeyH, true) => self.toggle_shader_hot_reload(),
+            (KeyCode::KeyI, true) => self.toggle_stats(),
+            (KeyCode::F12, true) => self.capture_frame(),
+            #[cfg(not(target_arch = "wasm32"))]
+            (KeyCode::KeyG, true) => self.toggle_recording(),
+            (KeyCode::KeyQ, true) => self.set_reflection_quality(self.reflection_quality.next()),
+            (KeyCode::KeyE, true) => self.cycle_environment_update_interval(),
+            (KeyCode::KeyR, true) => self.toggle_ssr(),
+            (KeyCode::KeyO, true) => {
+                self.occlusion.set_enabled(!self.occlusion.enabled());
+                log::info!("mirror occlusion queries: {}", self.occlusion.enabled());
+            }
+            (KeyCode::KeyT, true) => self.set_mirror_technique(match self.mirror_technique {
+                MirrorTechnique::Stencil => MirrorTechnique::RenderTexture,
+                MirrorTechnique::RenderTexture => MirrorTechnique::Stencil,
+            }),
+            (KeyCode::BracketRight, true) => self.set_reflection_depth(self.reflection_depth + 1),
+            (KeyCode::BracketLeft, true) => {
+                self.set_reflection_depth(self.reflection_depth.saturating_sub(1))
+            }
             (KeyCode::Escape, true) => event_loop.exit(),
             _ => {}
         }
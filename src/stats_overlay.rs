@@ -0,0 +1,128 @@
+use bytemuck::Zeroable;
+use wgpu::util::DeviceExt;
+
+// / S T A T S   O V E R L A Y
+// / Lines of text in the top left corner of the frame, drawn by one fullscreen pass
+// / (`stats_overlay.wgsl`) from a grid of 3x5 pixel glyphs. Upper case, digits and a little
+// / punctuation; lower case is shown in upper case.
+
+pub const COLUMNS: usize = 48;
+pub const ROWS: usize = 20;
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct StatsTextUniform {
+    // Columns and rows in use, screen pixels per glyph pixel
+    grid: [u32; 4],
+    // The glyph of each cell, row by row, 4 to a vec4
+    glyphs: [[u32; 4]; COLUMNS * ROWS / 4],
+}
+
+pub struct StatsOverlay {
+    buffer: wgpu::Buffer,
+    pub bind_group: wgpu::BindGroup,
+}
+
+impl StatsOverlay {
+    pub fn new(device: &wgpu::Device) -> Self {
+        let buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Stats Text Buffer"),
+            contents: bytemuck::bytes_of(&StatsTextUniform::zeroed()),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("stats_text_bind_group"),
+            layout: &Self::bind_group_layout(device),
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: buffer.as_entire_binding(),
+            }],
+        });
+        Self { buffer, bind_group }
+    }
+
+    pub fn bind_group_layout(device: &wgpu::Device) -> wgpu::BindGroupLayout {
+        device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("stats_text_bind_group_layout"),
+            entries: &[wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            }],
+        })
+    }
+
+    // Lines past ROWS, and characters past COLUMNS, are cut.
+    pub fn set_text(&self, queue: &wgpu::Queue, lines: &[String], scale: u32) {
+        let mut text = StatsTextUniform::zeroed();
+        let rows = lines.len().min(ROWS);
+        let mut columns = 0;
+        for (row, line) in lines.iter().take(rows).enumerate() {
+            for (column, c) in line.chars().take(COLUMNS).enumerate() {
+                let cell = row * COLUMNS + column;
+                text.glyphs[cell / 4][cell % 4] = glyph(c);
+                columns = columns.max(column + 1);
+            }
+        }
+        text.grid = [columns as u32, rows as u32, scale.max(1), 0];
+        queue.write_buffer(&self.buffer, 0, bytemuck::bytes_of(&text));
+    }
+}
+
+// 3 columns x 5 rows, top row first, each row's leftmost pixel in its highest bit.
+fn glyph(c: char) -> u32 {
+    match c.to_ascii_uppercase() {
+        ' ' => 0,
+        '0' => 0b111_101_101_101_111,
+        '1' => 0b010_110_010_010_111,
+        '2' => 0b111_001_111_100_111,
+        '3' => 0b111_001_111_001_111,
+        '4' => 0b101_101_111_001_001,
+        '5' => 0b111_100_111_001_111,
+        '6' => 0b111_100_111_101_111,
+        '7' => 0b111_001_001_001_001,
+        '8' => 0b111_101_111_101_111,
+        '9' => 0b111_101_111_001_111,
+        'A' => 0b010_101_111_101_101,
+        'B' => 0b110_101_110_101_110,
+        'C' => 0b011_100_100_100_011,
+        'D' => 0b110_101_101_101_110,
+        'E' => 0b111_100_110_100_111,
+        'F' => 0b111_100_110_100_100,
+        'G' => 0b011_100_101_101_011,
+        'H' => 0b101_101_111_101_101,
+        'I' => 0b111_010_010_010_111,
+        'J' => 0b001_001_001_101_010,
+        'K' => 0b101_101_110_101_101,
+        'L' => 0b100_100_100_100_111,
+        'M' => 0b101_111_111_101_101,
+        'N' => 0b110_101_101_101_101,
+        'O' => 0b010_101_101_101_010,
+        'P' => 0b110_101_110_100_100,
+        'Q' => 0b010_101_101_110_011,
+        'R' => 0b110_101_110_101_101,
+        'S' => 0b011_100_010_001_110,
+        'T' => 0b111_010_010_010_010,
+        'U' => 0b101_101_101_101_111,
+        'V' => 0b101_101_101_101_010,
+        'W' => 0b101_101_111_111_101,
+        'X' => 0b101_101_010_101_101,
+        'Y' => 0b101_101_010_010_010,
+        'Z' => 0b111_001_010_100_111,
+        '.' => 0b000_000_000_000_010,
+        ',' => 0b000_000_000_010_100,
+        ':' => 0b000_010_000_010_000,
+        '-' => 0b000_000_111_000_000,
+        '_' => 0b000_000_000_000_111,
+        '/' => 0b001_001_010_100_100,
+        '(' => 0b001_010_010_010_001,
+        ')' => 0b100_010_010_010_100,
+        '%' => 0b101_001_010_100_101,
+        _ => 0b111_001_010_000_010, // ?
+    }
+}
@@ -0,0 +1,267 @@
+use std::sync::Arc;
+
+use winit::window::Window;
+
+use crate::offscreen::OffscreenTarget;
+
+// Shaders write linear colors and count on the target to encode them as sRGB, so the
+// frame is rendered in an sRGB format: the surface's own, or an sRGB view of it when
+// the surface only comes in plain formats (WebGPU canvases: Bgra8Unorm, Rgba8Unorm).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SurfaceFormats {
+    // What the surface is configured with
+    pub surface: wgpu::TextureFormat,
+    // What the frame is rendered in: `config.format`, for every target and pipeline
+    pub render: wgpu::TextureFormat,
+}
+
+impl SurfaceFormats {
+    pub fn negotiate(caps: &wgpu::SurfaceCapabilities, adapter: &wgpu::Adapter) -> Self {
+        if let Some(&format) = caps.formats.iter().find(|format| format.is_srgb()) {
+            return Self::same(format);
+        }
+
+        let srgb_views = adapter
+            .get_downlevel_capabilities()
+            .flags
+            .contains(wgpu::DownlevelFlags::SURFACE_VIEW_FORMATS);
+        let srgb_view = caps
+            .formats
+            .iter()
+            .find(|format| format.add_srgb_suffix() != **format);
+        if let Some(&format) = srgb_view.filter(|_| srgb_views) {
+            return Self {
+                surface: format,
+                render: format.add_srgb_suffix(),
+            };
+        }
+
+        // Float surfaces take linear colors as they are
+        let linear = caps
+            .formats
+            .iter()
+            .copied()
+            .find(|&format| is_float(format));
+        let format = linear.unwrap_or(caps.formats[0]);
+        if !is_float(format) {
+            log::warn!("surface format {format:?} has no sRGB view: colors will come out darker");
+        }
+        Self::same(format)
+    }
+
+    fn same(format: wgpu::TextureFormat) -> Self {
+        Self {
+            surface: format,
+            render: format,
+        }
+    }
+
+    // `config`, in the render format, as the surface takes it.
+    pub fn configure(
+        &self,
+        surface: &wgpu::Surface,
+        device: &wgpu::Device,
+        config: &wgpu::SurfaceConfiguration,
+    ) {
+        let mut config = config.clone();
+        config.format = self.surface;
+        if self.render != self.surface {
+            config.view_formats = vec![self.render];
+        }
+        surface.configure(device, &config);
+    }
+
+    // A view of the surface texture in the render format.
+    pub fn view(&self, texture: &wgpu::Texture) -> wgpu::TextureView {
+        texture.create_view(&wgpu::TextureViewDescriptor {
+            format: Some(self.render),
+            ..Default::default()
+        })
+    }
+}
+
+fn is_float(format: wgpu::TextureFormat) -> bool {
+    matches!(
+        format,
+        wgpu::TextureFormat::Rgba16Float | wgpu::TextureFormat::Rgba32Float
+    )
+}
+
+// Vsync on: the frame rate follows the display, the GPU idles in between.
+pub const DEFAULT_PRESENT_MODE: wgpu::PresentMode = wgpu::PresentMode::Fifo;
+pub const DEFAULT_FRAME_LATENCY: u32 = 2;
+
+// `requested` when the surface has it. Otherwise Mailbox and Immediate stand in for each
+// other, both leaving the frame rate uncapped; Fifo is always supported.
+pub fn present_mode_fallback(
+    supported: &[wgpu::PresentMode],
+    requested: wgpu::PresentMode,
+) -> wgpu::PresentMode {
+    use wgpu::PresentMode::{Fifo, Immediate, Mailbox};
+    let candidates: &[wgpu::PresentMode] = match requested {
+        Immediate => &[Immediate, Mailbox],
+        Mailbox => &[Mailbox, Immediate],
+        _ => &[requested],
+    };
+    candidates
+        .iter()
+        .copied()
+        .find(|mode| supported.contains(mode))
+        .unwrap_or(Fifo)
+}
+
+pub fn is_vsync(mode: wgpu::PresentMode) -> bool {
+    matches!(
+        mode,
+        wgpu::PresentMode::Fifo | wgpu::PresentMode::FifoRelaxed | wgpu::PresentMode::AutoVsync
+    )
+}
+
+// / F R A M E   T A R G E T S
+
+// Where `State` renders: a window's surface, or a texture when there is no display.
+pub enum FrameTarget {
+    Window(WindowSurface),
+    Offscreen(OffscreenTarget),
+}
+
+// The texture a frame is drawn into, presented (for a surface) once it is submitted.
+pub struct Frame {
+    pub texture: wgpu::Texture,
+    pub view: wgpu::TextureView,
+    surface_texture: Option<wgpu::SurfaceTexture>,
+}
+
+impl Frame {
+    pub fn present(self) {
+        if let Some(surface_texture) = self.surface_texture {
+            surface_texture.present();
+        }
+    }
+}
+
+impl FrameTarget {
+    pub fn window(&self) -> Option<&Arc<Window>> {
+        match self {
+            Self::Window(window_surface) => Some(&window_surface.window),
+            Self::Offscreen(_) => None,
+        }
+    }
+
+    // None while a window's surface isn't configured yet.
+    pub fn acquire(&self) -> Result<Option<Frame>, wgpu::SurfaceError> {
+        match self {
+            Self::Window(window_surface) if !window_surface.configured => Ok(None),
+            Self::Window(window_surface) => {
+                let surface_texture = window_surface.surface.get_current_texture()?;
+                Ok(Some(Frame {
+                    texture: surface_texture.texture.clone(),
+                    view: window_surface.formats.view(&surface_texture.texture),
+                    surface_texture: Some(surface_texture),
+                }))
+            }
+            Self::Offscreen(offscreen) => Ok(Some(Frame {
+                texture: offscreen.texture.clone(),
+                view: offscreen.texture.create_view(&Default::default()),
+                surface_texture: None,
+            })),
+        }
+    }
+
+    pub fn resize(&mut self, device: &wgpu::Device, config: &wgpu::SurfaceConfiguration) {
+        match self {
+            Self::Window(window_surface) => {
+                window_surface.configured = true;
+                window_surface.configure(device, config);
+            }
+            Self::Offscreen(offscreen) => *offscreen = OffscreenTarget::new(device, config),
+        }
+    }
+
+    // In the window title, in place of the title, until cleared with None.
+    pub fn set_status(&self, status: Option<&str>) {
+        if let Self::Window(window_surface) = self {
+            let title = status.unwrap_or(&window_surface.title);
+            window_surface.window.set_title(title);
+        }
+    }
+}
+
+// A window, its surface and how the surface presents.
+pub struct WindowSurface {
+    pub window: Arc<Window>,
+    pub surface: wgpu::Surface<'static>,
+    pub formats: SurfaceFormats,
+    // What the surface can present with, for `set_present_mode`
+    present_modes: Vec<wgpu::PresentMode>,
+    // Where turning vsync off goes back to: Mailbox or Immediate
+    uncapped_present_mode: wgpu::PresentMode,
+    // Not before the first resize
+    configured: bool,
+    title: String,
+}
+
+impl WindowSurface {
+    pub fn new(
+        window: Arc<Window>,
+        surface: wgpu::Surface<'static>,
+        formats: SurfaceFormats,
+        present_modes: Vec<wgpu::PresentMode>,
+    ) -> Self {
+        Self {
+            title: window.title(),
+            window,
+            surface,
+            formats,
+            present_modes,
+            uncapped_present_mode: wgpu::PresentMode::Immediate,
+            configured: false,
+        }
+    }
+
+    fn configure(&self, device: &wgpu::Device, config: &wgpu::SurfaceConfiguration) {
+        if self.configured {
+            self.formats.configure(&self.surface, device, config);
+        }
+    }
+
+    // Fifo (vsync), Mailbox or Immediate; an unsupported mode falls back to the closest.
+    pub fn set_present_mode(
+        &mut self,
+        device: &wgpu::Device,
+        config: &mut wgpu::SurfaceConfiguration,
+        requested: wgpu::PresentMode,
+    ) {
+        let mode = present_mode_fallback(&self.present_modes, requested);
+        if mode != requested {
+            log::warn!("present mode: {requested:?} is not supported, using {mode:?}");
+        }
+        if !is_vsync(mode) {
+            self.uncapped_present_mode = mode;
+        }
+        config.present_mode = mode;
+        self.configure(device, config);
+        log::info!("present mode: {mode:?}");
+    }
+
+    // Vsync to save power, off for uncapped frame rates (benchmarks).
+    pub fn toggle_vsync(&mut self, device: &wgpu::Device, config: &mut wgpu::SurfaceConfiguration) {
+        let mode = if is_vsync(config.present_mode) {
+            self.uncapped_present_mode
+        } else {
+            wgpu::PresentMode::Fifo
+        };
+        self.set_present_mode(device, config, mode);
+    }
+
+    pub fn set_frame_latency(
+        &mut self,
+        device: &wgpu::Device,
+        config: &mut wgpu::SurfaceConfiguration,
+        frames: u32,
+    ) {
+        config.desired_maximum_frame_latency = frames.max(1);
+        self.configure(device, config);
+        log::info!("frame latency: {}", config.desired_maximum_frame_latency);
+    }
+}
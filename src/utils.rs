@@ -108,6 +108,34 @@ fn reflection_matrix(
     )
 }
 
+// Replace the near plane of an OpenGL-style projection with `plane`, given in view space
+// (E. Lengyel, "Oblique View Frustum Depth Projection and Clipping").
+// Only valid when the camera sits on the negative side of the plane.
+pub fn oblique_near_plane(
+    projection: cgmath::Matrix4<f32>,
+    plane: cgmath::Vector4<f32>,
+) -> Option<cgmath::Matrix4<f32>> {
+    use cgmath::InnerSpace;
+    use cgmath::SquareMatrix;
+
+    if plane.w >= 0.0 {
+        return None;
+    }
+
+    // Frustum corner opposite the plane, back in view space
+    let corner = cgmath::Vector4::new(plane.x.signum(), plane.y.signum(), 1.0, 1.0);
+    let q = projection.invert()? * corner;
+    let c = plane * (2.0 / plane.dot(q));
+
+    // Third row = c - fourth row
+    let mut oblique = projection;
+    oblique.x.z = c.x - oblique.x.w;
+    oblique.y.z = c.y - oblique.y.w;
+    oblique.z.z = c.z - oblique.z.w;
+    oblique.w.z = c.w - oblique.w.w;
+    Some(oblique)
+}
+
 pub fn build_reflection_matrix(
     mirror_transform: &cgmath::Matrix4<f32>,
     local_normal: Vector3<f32>,
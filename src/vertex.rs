@@ -1,3 +1,5 @@
+use std::ops::Range;
+
 use anyhow::*;
 use cgmath::{prelude::*, Matrix4};
 use wgpu::util::DeviceExt;
@@ -102,9 +104,32 @@ const NUM_INSTANCES_PER_ROW: u32 = 2;
 //const INSTANCE_DISPLACEMENT: cgmath::Vector3<f32> = cgmath::Vector3::new(NUM_INSTANCES_PER_ROW as f32 * 0.5, 0.0, NUM_INSTANCES_PER_ROW as f32 * 0.5);
 const SPACE_BETWEEN: f32 = 3.0;
 
+// Which passes draw an instance: the main view, the reflections (mirrors, water,
+// cube maps), or both. Screen-space reflections only see what the main view drew.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum RenderLayer {
+    #[default]
+    All,
+    // e.g. a vampire: never in a mirror
+    DirectOnly,
+    // e.g. the first-person player body: in the mirror, never in front of the camera
+    ReflectionOnly,
+}
+
+impl RenderLayer {
+    pub fn direct(self) -> bool {
+        self != Self::ReflectionOnly
+    }
+
+    pub fn reflected(self) -> bool {
+        self != Self::DirectOnly
+    }
+}
+
 pub struct Instance {
     position: cgmath::Vector3<f32>,
     rotation: cgmath::Quaternion<f32>,
+    pub layer: RenderLayer,
 }
 
 impl Instance {
@@ -115,6 +140,7 @@ impl Instance {
             .into(),
         }
     }
+    // Scales x and y only, for flat quads in the xy plane.
     pub fn to_raw_with_scale(&self, scale: f32) -> InstanceRaw {
         InstanceRaw {
             model: (cgmath::Matrix4::from_translation(self.position)
@@ -123,6 +149,15 @@ impl Instance {
             .into(),
         }
     }
+    // Scales all three axes, for meshes with depth (OBJ mirrors, the chrome sphere).
+    pub fn to_raw_with_uniform_scale(&self, scale: f32) -> InstanceRaw {
+        InstanceRaw {
+            model: (cgmath::Matrix4::from_translation(self.position)
+                * cgmath::Matrix4::from(self.rotation)
+                * Matrix4::from_scale(scale))
+            .into(),
+        }
+    }
     pub fn generate_instances() -> Vec<Instance> {
         (0..NUM_INSTANCES_PER_ROW)
             .flat_map(|z| {
@@ -141,7 +176,11 @@ impl Instance {
                         cgmath::Quaternion::from_axis_angle(position.normalize(), cgmath::Deg(45.0))
                     };
 
-                    Instance { position, rotation }
+                    Instance {
+                        position,
+                        rotation,
+                        layer: RenderLayer::All,
+                    }
                 })
             })
             .collect::<Vec<_>>()
@@ -153,7 +192,15 @@ impl Instance {
         let rotation =
             cgmath::Quaternion::from_axis_angle(cgmath::Vector3::unit_y(), cgmath::Deg(angle));
 
-        Instance { position, rotation }
+        Instance {
+            position,
+            rotation,
+            layer: RenderLayer::All,
+        }
+    }
+
+    pub fn with_layer(self, layer: RenderLayer) -> Self {
+        Self { layer, ..self }
     }
 
     pub fn translation(&self) -> cgmath::Matrix4<f32> {
@@ -214,6 +261,54 @@ impl InstanceRaw {
     }
 }
 
+// Instances sharing one mesh, stored direct-only, then all, then reflection-only, so
+// the main pass and the reflection passes each draw one contiguous range.
+pub struct LayeredInstances {
+    pub instances: Vec<Instance>,
+    pub buffer: wgpu::Buffer,
+    direct: Range<u32>,
+    reflected: Range<u32>,
+}
+
+impl LayeredInstances {
+    pub fn new(device: &wgpu::Device, mut instances: Vec<Instance>) -> Self {
+        let order = |layer: RenderLayer| match layer {
+            RenderLayer::DirectOnly => 0,
+            RenderLayer::All => 1,
+            RenderLayer::ReflectionOnly => 2,
+        };
+        // Stable: instances keep their order within a layer
+        instances.sort_by_key(|instance| order(instance.layer));
+
+        let count = |keep: fn(RenderLayer) -> bool| {
+            instances.iter().filter(|i| keep(i.layer)).count() as u32
+        };
+        let total = instances.len() as u32;
+        let direct = 0..count(RenderLayer::direct);
+        let reflected = total - count(RenderLayer::reflected)..total;
+
+        let instance_data = instances.iter().map(Instance::to_raw).collect::<Vec<_>>();
+        let buffer = create_instance_buffer(device, &instance_data);
+
+        Self {
+            instances,
+            buffer,
+            direct,
+            reflected,
+        }
+    }
+
+    // Instances seen by the camera itself
+    pub fn direct(&self) -> Range<u32> {
+        self.direct.clone()
+    }
+
+    // Instances seen in mirrors, water and cube maps
+    pub fn reflected(&self) -> Range<u32> {
+        self.reflected.clone()
+    }
+}
+
 pub fn create_instance_buffer(
     device: &wgpu::Device,
     instance_data: &[InstanceRaw],
@@ -0,0 +1,160 @@
+use std::sync::{Arc, Mutex};
+
+use cgmath::{EuclideanSpace, InnerSpace, Matrix, Point3, Vector4};
+
+// View frustum as six inward-facing planes (wgpu clip space, z in 0..1).
+pub struct Frustum {
+    planes: [Vector4<f32>; 6],
+}
+
+impl Frustum {
+    pub fn from_view_proj(view_proj: cgmath::Matrix4<f32>) -> Self {
+        let (r0, r1, r2, r3) = (
+            view_proj.row(0),
+            view_proj.row(1),
+            view_proj.row(2),
+            view_proj.row(3),
+        );
+        let planes = [r3 + r0, r3 - r0, r3 + r1, r3 - r1, r2, r3 - r2]
+            .map(|plane| plane / plane.truncate().magnitude());
+        Self { planes }
+    }
+
+    pub fn intersects_sphere(&self, center: Point3<f32>, radius: f32) -> bool {
+        self.planes
+            .iter()
+            .all(|plane| plane.truncate().dot(center.to_vec()) + plane.w >= -radius)
+    }
+}
+
+// One occlusion query per mirror, read back one frame later.
+//
+// The queries wrap the mirror surface draws, the last pass to test against the full
+// depth buffer. A mirror whose query found no sample is skipped the next frame, but
+// keeps a depth-only probe in its place so it can come back.
+pub struct OcclusionQueries {
+    enabled: bool,
+    query_set: wgpu::QuerySet,
+    resolve_buffer: wgpu::Buffer,
+    readback_buffer: wgpu::Buffer,
+    // Mirrors queried in the batch being read back, if any.
+    in_flight: Option<Vec<bool>>,
+    map_requested: bool,
+    // Outcome of the pending map_async: Some(true) once the results can be read.
+    mapped: Arc<Mutex<Option<bool>>>,
+    visible: Vec<bool>,
+}
+
+impl OcclusionQueries {
+    pub fn new(device: &wgpu::Device, count: usize) -> Self {
+        let count = count.max(1);
+        let size = (count * std::mem::size_of::<u64>()) as wgpu::BufferAddress;
+
+        let query_set = device.create_query_set(&wgpu::QuerySetDescriptor {
+            label: Some("mirror occlusion queries"),
+            ty: wgpu::QueryType::Occlusion,
+            count: count as u32,
+        });
+        let resolve_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Occlusion Resolve Buffer"),
+            size,
+            usage: wgpu::BufferUsages::QUERY_RESOLVE | wgpu::BufferUsages::COPY_SRC,
+            mapped_at_creation: false,
+        });
+        let readback_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Occlusion Readback Buffer"),
+            size,
+            usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
+            mapped_at_creation: false,
+        });
+
+        Self {
+            enabled: true,
+            query_set,
+            resolve_buffer,
+            readback_buffer,
+            in_flight: None,
+            map_requested: false,
+            mapped: Arc::new(Mutex::new(None)),
+            visible: vec![true; count],
+        }
+    }
+
+    // Collect last frame's results if they arrived. True when a new batch can be
+    // queried this frame, i.e. the readback buffer is free again.
+    pub fn begin_frame(&mut self, device: &wgpu::Device) -> bool {
+        if self.in_flight.is_some() {
+            let _ = device.poll(wgpu::PollType::Poll);
+        }
+
+        let mapped = self.mapped.lock().unwrap().take();
+        match (mapped, self.in_flight.take()) {
+            (Some(true), Some(queried)) => {
+                let data = self.readback_buffer.slice(..).get_mapped_range();
+                let samples: &[u64] = bytemuck::cast_slice(&data);
+                for (i, (&queried, &samples)) in queried.iter().zip(samples).enumerate() {
+                    self.visible[i] = !queried || samples > 0;
+                }
+                drop(data);
+                self.readback_buffer.unmap();
+            }
+            // Lost batch: show everything rather than trust old results
+            (Some(false), _) => self.visible.fill(true),
+            (_, in_flight) => self.in_flight = in_flight,
+        }
+
+        self.enabled && self.in_flight.is_none()
+    }
+
+    pub fn query_set(&self) -> &wgpu::QuerySet {
+        &self.query_set
+    }
+
+    // `queried[i]`: mirror i was drawn or probed inside its query this frame. The others
+    // count as visible again, so they don't pop in late when they come back into view.
+    pub fn resolve(&mut self, encoder: &mut wgpu::CommandEncoder, queried: Vec<bool>) {
+        encoder.resolve_query_set(
+            &self.query_set,
+            0..self.visible.len() as u32,
+            &self.resolve_buffer,
+            0,
+        );
+        encoder.copy_buffer_to_buffer(
+            &self.resolve_buffer,
+            0,
+            &self.readback_buffer,
+            0,
+            self.resolve_buffer.size(),
+        );
+        self.in_flight = Some(queried);
+        self.map_requested = false;
+    }
+
+    // Once the resolve is submitted.
+    pub fn map(&mut self) {
+        if self.in_flight.is_none() || self.map_requested {
+            return;
+        }
+        self.map_requested = true;
+        let mapped = self.mapped.clone();
+        self.readback_buffer
+            .slice(..)
+            .map_async(wgpu::MapMode::Read, move |result| {
+                *mapped.lock().unwrap() = Some(result.is_ok());
+            });
+    }
+
+    pub fn is_visible(&self, mirror: usize) -> bool {
+        !self.enabled || self.visible[mirror]
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.enabled
+    }
+
+    pub fn set_enabled(&mut self, enabled: bool) {
+        self.enabled = enabled;
+        // Stale results would hide mirrors for a frame when turned back on
+        self.visible.fill(true);
+    }
+}
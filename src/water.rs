@@ -0,0 +1,289 @@
+use cgmath::{EuclideanSpace, InnerSpace, SquareMatrix, Vector3};
+use wgpu::util::DeviceExt;
+
+use crate::{
+    camera::{
+        bind_group_for_camera_uniform, create_camera_reflected_buffer, Camera, CameraUniform,
+    },
+    extra::{MirrorPlaneUniform, WaterUniform},
+    mirror::{MirrorClipping, ReflectionTexture},
+    model::{DrawModel, Model},
+    resources,
+    texture::Texture,
+    utils::{build_reflection_matrix, normal_from_transform, point_from_transform},
+    vertex::{create_instance_buffer, Instance},
+};
+
+// Planar water: the scene above the surface is rendered into a reflection texture, the
+// scene below it into a refraction texture, and water.wgsl mixes them with Fresnel.
+pub struct Water {
+    model: Model,
+    pub instance: Instance,
+    instance_buffer: wgpu::Buffer,
+    pub uniform: WaterUniform,
+    buffer: wgpu::Buffer,
+    normal_map: Texture,
+    normal_sampler: wgpu::Sampler,
+    pub reflection: ReflectionTexture,
+    pub refraction: ReflectionTexture,
+    pub bind_group: wgpu::BindGroup,
+    // Keeps the scene above the water in the reflection
+    reflection_plane: MirrorPlaneUniform,
+    reflection_plane_buffer: wgpu::Buffer,
+    pub reflection_plane_bind_group: wgpu::BindGroup,
+    // Same plane, flipped: keeps the scene below the water in the refraction
+    refraction_plane: MirrorPlaneUniform,
+    refraction_plane_buffer: wgpu::Buffer,
+    pub refraction_plane_bind_group: wgpu::BindGroup,
+    reflected_camera_buffer: wgpu::Buffer,
+    pub reflected_camera_bind_group: wgpu::BindGroup,
+    refracted_camera_buffer: wgpu::Buffer,
+    pub refracted_camera_bind_group: wgpu::BindGroup,
+}
+
+impl Water {
+    // `file_name` is a flat mesh facing +Y, e.g. "models/water.obj".
+    pub async fn new(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        config: &wgpu::SurfaceConfiguration,
+        file_name: &str,
+        instance: Instance,
+        layout: &wgpu::BindGroupLayout,
+    ) -> anyhow::Result<Self> {
+        let model = resources::load_model(file_name, device, queue, layout).await?;
+        let instance_buffer = create_instance_buffer(device, &[instance.to_raw()]);
+
+        let uniform = WaterUniform::new([0.45, 0.75, 0.8], 0.02, 0.03, 0.02);
+        let buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Water Buffer"),
+            contents: bytemuck::cast_slice(&[uniform]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+
+        let normal_map =
+            Texture::get_texture_from_image(device, queue, "images/water-normal.png").await?;
+        // The normal map tiles across the surface
+        let normal_sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            address_mode_u: wgpu::AddressMode::Repeat,
+            address_mode_v: wgpu::AddressMode::Repeat,
+            address_mode_w: wgpu::AddressMode::Repeat,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            mipmap_filter: wgpu::FilterMode::Nearest,
+            ..Default::default()
+        });
+
+        let reflection = ReflectionTexture::new(device, config);
+        let refraction = ReflectionTexture::new(device, config);
+        let bind_group = Self::create_bind_group(
+            device,
+            &buffer,
+            &normal_map,
+            &normal_sampler,
+            &reflection,
+            &refraction,
+        );
+
+        // / W A T E R  P L A N E S
+        let transform = instance.transform();
+        let reflection_plane = MirrorPlaneUniform::new(&transform, Vector3::unit_y());
+        let reflection_plane_buffer = reflection_plane.mirror_plane_buffer(device);
+        let (_, reflection_plane_bind_group) =
+            MirrorPlaneUniform::create_bind_group_layout(device, &reflection_plane_buffer);
+        let refraction_plane = MirrorPlaneUniform::new(&transform, -Vector3::unit_y());
+        let refraction_plane_buffer = refraction_plane.mirror_plane_buffer(device);
+        let (_, refraction_plane_bind_group) =
+            MirrorPlaneUniform::create_bind_group_layout(device, &refraction_plane_buffer);
+
+        // / C A M E R A S
+        let reflected_camera_buffer = create_camera_reflected_buffer(&CameraUniform::new(), device);
+        let (_, reflected_camera_bind_group) =
+            bind_group_for_camera_uniform(&reflected_camera_buffer, device);
+        let refracted_camera_buffer = create_camera_reflected_buffer(&CameraUniform::new(), device);
+        let (_, refracted_camera_bind_group) =
+            bind_group_for_camera_uniform(&refracted_camera_buffer, device);
+
+        Ok(Self {
+            model,
+            instance,
+            instance_buffer,
+            uniform,
+            buffer,
+            normal_map,
+            normal_sampler,
+            reflection,
+            refraction,
+            bind_group,
+            reflection_plane,
+            reflection_plane_buffer,
+            reflection_plane_bind_group,
+            refraction_plane,
+            refraction_plane_buffer,
+            refraction_plane_bind_group,
+            reflected_camera_buffer,
+            reflected_camera_bind_group,
+            refracted_camera_buffer,
+            refracted_camera_bind_group,
+        })
+    }
+
+    pub fn bind_group_layout(device: &wgpu::Device) -> wgpu::BindGroupLayout {
+        let texture = |binding| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::FRAGMENT,
+            ty: wgpu::BindingType::Texture {
+                multisampled: false,
+                view_dimension: wgpu::TextureViewDimension::D2,
+                sample_type: wgpu::TextureSampleType::Float { filterable: true },
+            },
+            count: None,
+        };
+        let sampler = |binding| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::FRAGMENT,
+            ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+            count: None,
+        };
+
+        device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("water_bind_group_layout"),
+            entries: &[
+                texture(0), // reflection
+                texture(1), // refraction
+                sampler(2), // clamped, for both
+                texture(3), // normal map
+                sampler(4), // repeating
+                wgpu::BindGroupLayoutEntry {
+                    binding: 5,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+            ],
+        })
+    }
+
+    fn create_bind_group(
+        device: &wgpu::Device,
+        buffer: &wgpu::Buffer,
+        normal_map: &Texture,
+        normal_sampler: &wgpu::Sampler,
+        reflection: &ReflectionTexture,
+        refraction: &ReflectionTexture,
+    ) -> wgpu::BindGroup {
+        device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("water_bind_group"),
+            layout: &Self::bind_group_layout(device),
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(&reflection.color.view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::TextureView(&refraction.color.view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: wgpu::BindingResource::Sampler(&reflection.color.sampler),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: wgpu::BindingResource::TextureView(&normal_map.view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 4,
+                    resource: wgpu::BindingResource::Sampler(normal_sampler),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 5,
+                    resource: buffer.as_entire_binding(),
+                },
+            ],
+        })
+    }
+
+    // The offscreen targets follow the window size.
+    pub fn resize(&mut self, device: &wgpu::Device, config: &wgpu::SurfaceConfiguration) {
+        self.reflection = ReflectionTexture::new(device, config);
+        self.refraction = ReflectionTexture::new(device, config);
+        self.bind_group = Self::create_bind_group(
+            device,
+            &self.buffer,
+            &self.normal_map,
+            &self.normal_sampler,
+            &self.reflection,
+            &self.refraction,
+        );
+    }
+
+    // World-space plane (normal, -normal . point), positive above the water.
+    fn clip_plane(&self) -> cgmath::Vector4<f32> {
+        let transform = self.instance.transform();
+        let normal = normal_from_transform(&transform, Vector3::unit_y());
+        let point = point_from_transform(&transform);
+        normal.extend(-normal.dot(point.to_vec()))
+    }
+
+    pub fn animate(&mut self, dt: f32) {
+        self.uniform.advance(dt);
+    }
+
+    // Write both cameras, the clip planes and the wave time for this frame.
+    pub fn update(&mut self, queue: &wgpu::Queue, camera: &Camera, clipping: MirrorClipping) {
+        let oblique = clipping == MirrorClipping::Oblique;
+        let clip_plane = self.clip_plane();
+
+        let reflection = build_reflection_matrix(&self.instance.transform(), Vector3::unit_y());
+        let reflected_eye = {
+            use cgmath::Transform;
+            reflection.transform_point(camera.eye)
+        };
+        let (reflected_view_proj, reflection_clipped) =
+            camera.build_reflected_camera(reflection, oblique.then_some(clip_plane));
+        let reflected_camera = CameraUniform::from_view_proj(reflected_view_proj, reflected_eye);
+        // Not reflected, only clipped on the other side
+        let (refracted_view_proj, refraction_clipped) = camera
+            .build_reflected_camera(cgmath::Matrix4::identity(), oblique.then_some(-clip_plane));
+        let refracted_camera = CameraUniform::from_view_proj(refracted_view_proj, camera.eye);
+
+        // Discard wherever the oblique near plane couldn't be applied, e.g. from under water
+        self.reflection_plane.set_clip_discard(!reflection_clipped);
+        self.refraction_plane.set_clip_discard(!refraction_clipped);
+
+        queue.write_buffer(
+            &self.reflected_camera_buffer,
+            0,
+            bytemuck::cast_slice(&[reflected_camera]),
+        );
+        queue.write_buffer(
+            &self.refracted_camera_buffer,
+            0,
+            bytemuck::cast_slice(&[refracted_camera]),
+        );
+        queue.write_buffer(
+            &self.reflection_plane_buffer,
+            0,
+            bytemuck::cast_slice(&[self.reflection_plane]),
+        );
+        queue.write_buffer(
+            &self.refraction_plane_buffer,
+            0,
+            bytemuck::cast_slice(&[self.refraction_plane]),
+        );
+        queue.write_buffer(&self.buffer, 0, bytemuck::cast_slice(&[self.uniform]));
+    }
+
+    // Water mesh at its instance, vertex buffer 1 being the instance slot.
+    pub fn draw<'a>(&'a self, pass: &mut wgpu::RenderPass<'a>) {
+        pass.set_vertex_buffer(1, self.instance_buffer.slice(..));
+        for mesh in &self.model.meshes {
+            pass.draw_mesh(mesh);
+        }
+    }
+}
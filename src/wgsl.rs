@@ -0,0 +1,214 @@
+use std::{borrow::Cow, collections::HashSet};
+
+use anyhow::{bail, Context};
+
+// A small preprocessor run on every shader before it reaches wgpu:
+//
+//   #import "common/camera.wgsl"   the file's text, once per shader, from src/shaders
+//   #define NAME                   a flag for the rest of the shader, imports included
+//   #ifdef NAME / #ifndef NAME     keep the lines up to #else / #endif when NAME is
+//   #else / #endif                 (not) defined; these nest
+//
+// Flags also come from the pipeline description (`Shader::define`), which is how one
+// file gives several variants.
+pub struct Preprocessor<'a> {
+    defines: HashSet<Cow<'a, str>>,
+    imported: HashSet<String>,
+    load: &'a dyn Fn(&str) -> Option<Cow<'static, str>>,
+}
+
+impl<'a> Preprocessor<'a> {
+    // `load` finds an imported file by its path under src/shaders
+    pub fn new(defines: &[&'a str], load: &'a dyn Fn(&str) -> Option<Cow<'static, str>>) -> Self {
+        Self {
+            defines: defines.iter().map(|&name| Cow::Borrowed(name)).collect(),
+            imported: HashSet::new(),
+            load,
+        }
+    }
+
+    pub fn run(mut self, file: &str, source: &str) -> anyhow::Result<String> {
+        let mut out = String::with_capacity(source.len());
+        self.expand(file, source, &mut out)?;
+        Ok(out)
+    }
+
+    fn expand(&mut self, file: &str, source: &str, out: &mut String) -> anyhow::Result<()> {
+        // One entry per open #ifdef: whether its current branch is kept
+        let mut branches: Vec<bool> = Vec::new();
+
+        for (number, line) in source.lines().enumerate() {
+            let at = || format!("{file}:{}", number + 1);
+            let kept = branches.iter().all(|&kept| kept);
+
+            let Some(directive) = line.trim_start().strip_prefix('#') else {
+                if kept {
+                    out.push_str(line);
+                    out.push('\n');
+                }
+                continue;
+            };
+
+            let (keyword, argument) = directive
+                .split_once(char::is_whitespace)
+                .map_or((directive, ""), |(keyword, argument)| {
+                    (keyword, argument.trim())
+                });
+            match keyword {
+                "ifdef" | "ifndef" => {
+                    let defined = self.defines.contains(name(argument).with_context(at)?);
+                    branches.push(defined == (keyword == "ifdef"));
+                }
+                "else" => match branches.last_mut() {
+                    Some(branch) => *branch = !*branch,
+                    None => bail!("{}: #else without #ifdef", at()),
+                },
+                "endif" => {
+                    if branches.pop().is_none() {
+                        bail!("{}: #endif without #ifdef", at());
+                    }
+                }
+                "define" if kept => {
+                    let name = name(argument).with_context(at)?;
+                    self.defines.insert(Cow::Owned(name.to_string()));
+                }
+                "import" if kept => {
+                    let path = argument
+                        .strip_prefix('"')
+                        .and_then(|path| path.strip_suffix('"'))
+                        .with_context(|| format!("{}: expected #import \"file.wgsl\"", at()))?;
+                    if self.imported.insert(path.to_string()) {
+                        let text = (self.load)(path)
+                            .with_context(|| format!("{}: no shader file {path:?}", at()))?;
+                        self.expand(path, &text, out)?;
+                    }
+                }
+                "define" | "import" => {}
+                _ => bail!("{}: unknown directive #{keyword}", at()),
+            }
+        }
+
+        if !branches.is_empty() {
+            bail!("{file}: #ifdef without #endif");
+        }
+        Ok(())
+    }
+}
+
+fn name(argument: &str) -> anyhow::Result<&str> {
+    match argument.split_whitespace().collect::<Vec<_>>()[..] {
+        [name] => Ok(name),
+        _ => bail!("expected one name, found {argument:?}"),
+    }
+}
+
+// The files shaders import, compiled in. Every file of src/shaders/common must be listed
+// (see the `bundles_every_common_file` test).
+pub fn bundled(path: &str) -> Option<Cow<'static, str>> {
+    let text = match path {
+        "common/camera.wgsl" => include_str!("shaders/common/camera.wgsl"),
+        "common/instance.wgsl" => include_str!("shaders/common/instance.wgsl"),
+        "common/mirror_material.wgsl" => include_str!("shaders/common/mirror_material.wgsl"),
+        "common/mirror_plane.wgsl" => include_str!("shaders/common/mirror_plane.wgsl"),
+        "common/scene_output.wgsl" => include_str!("shaders/common/scene_output.wgsl"),
+        "common/spin.wgsl" => include_str!("shaders/common/spin.wgsl"),
+        _ => return None,
+    };
+    Some(Cow::Borrowed(text))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn files(path: &str) -> Option<Cow<'static, str>> {
+        let text = match path {
+            "a.wgsl" => "a\n#import \"b.wgsl\"\n",
+            "b.wgsl" => "b\n",
+            _ => return None,
+        };
+        Some(Cow::Borrowed(text))
+    }
+
+    fn run(defines: &[&str], source: &str) -> anyhow::Result<String> {
+        Preprocessor::new(defines, &files).run("main.wgsl", source)
+    }
+
+    #[test]
+    fn nested_ifdef_else() {
+        let source = "\
+#ifdef A
+#ifdef B
+a and b
+#else
+a only
+#endif
+#else
+#ifndef B
+neither
+#endif
+#endif
+end
+";
+        assert_eq!(run(&["A"], source).unwrap(), "a only\nend\n");
+        assert_eq!(run(&["A", "B"], source).unwrap(), "a and b\nend\n");
+        assert_eq!(run(&[], source).unwrap(), "neither\nend\n");
+        assert_eq!(run(&["B"], source).unwrap(), "end\n");
+    }
+
+    #[test]
+    fn stray_endif() {
+        let error = run(&[], "x\n#endif\n").unwrap_err().to_string();
+        assert_eq!(error, "main.wgsl:2: #endif without #ifdef");
+    }
+
+    #[test]
+    fn unclosed_ifdef() {
+        let error = run(&[], "#ifdef A\n").unwrap_err().to_string();
+        assert_eq!(error, "main.wgsl: #ifdef without #endif");
+    }
+
+    #[test]
+    fn repeated_import_is_included_once() {
+        let source = "#import \"a.wgsl\"\n#import \"b.wgsl\"\n#import \"a.wgsl\"\nmain\n";
+        assert_eq!(run(&[], source).unwrap(), "a\nb\nmain\n");
+    }
+
+    #[test]
+    fn define_in_dropped_branch_is_ignored() {
+        let source = "\
+#ifdef MISSING
+#define FLAG
+#import \"a.wgsl\"
+#endif
+#ifdef FLAG
+defined
+#else
+not defined
+#endif
+";
+        assert_eq!(run(&[], source).unwrap(), "not defined\n");
+    }
+
+    #[test]
+    fn unknown_directive() {
+        let error = run(&[], "#pragma once\n").unwrap_err().to_string();
+        assert_eq!(error, "main.wgsl:1: unknown directive #pragma");
+    }
+
+    #[test]
+    fn bundles_every_common_file() {
+        let dir = concat!(env!("CARGO_MANIFEST_DIR"), "/src/shaders/common");
+        for entry in std::fs::read_dir(dir).unwrap() {
+            let path = entry.unwrap().path();
+            let name = path.file_name().unwrap().to_str().unwrap();
+            let text = bundled(&format!("common/{name}"));
+            assert!(text.is_some(), "common/{name} is missing from `bundled`");
+            assert_eq!(
+                text.unwrap(),
+                std::fs::read_to_string(&path).unwrap(),
+                "{name}"
+            );
+        }
+    }
+}
@@ -0,0 +1,175 @@
+// / G O L D E N   I M A G E S
+// / Canonical mirror scenes rendered headless on a fallback adapter and compared with the
+// / references in `tests/golden`. A failing scene writes `<name>-actual.png` and
+// / `<name>-diff.png` to `target/golden`; `UPDATE_GOLDEN=1 cargo test --test golden`
+// / rewrites the references.
+
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use cgmath::Point3;
+use explore::{offscreen::OffscreenDesc, state::State};
+
+const WIDTH: u32 = 256;
+const HEIGHT: u32 = 192;
+
+// CIE76 distance under which two colors look the same (a "just noticeable difference")
+const MAX_DELTA_E: f32 = 2.3;
+// Share of pixels allowed past it: rasterization differs by a few edge pixels between drivers
+const MAX_DIFFERING: f64 = 0.005;
+
+// One adapter at a time: software rasterizers use every core already
+static GPU: Mutex<()> = Mutex::new(());
+
+// The pentagon mirror stands at (5, 1, 2), facing +x+z; the cubes are behind its plane.
+#[test]
+fn mirror_facing_camera() {
+    golden(
+        "mirror-facing",
+        Point3::new(20.6, 1.0, 17.6),
+        Point3::new(5.0, 1.0, 2.0),
+    );
+}
+
+#[test]
+fn mirror_at_45_degrees() {
+    golden(
+        "mirror-at-45",
+        Point3::new(5.0, 1.0, 24.0),
+        Point3::new(5.0, 1.0, 2.0),
+    );
+}
+
+// Seen from behind, a mirror shows its back: nothing of the reflection may show.
+#[test]
+fn camera_behind_mirror() {
+    golden(
+        "camera-behind",
+        Point3::new(-10.6, 1.0, -13.6),
+        Point3::new(5.0, 1.0, 2.0),
+    );
+}
+
+// The cubes behind the mirror plane must be hidden by the mirror, not reflected or drawn
+// through it.
+#[test]
+fn object_behind_mirror() {
+    golden(
+        "object-behind",
+        Point3::new(24.0, 3.0, 6.0),
+        Point3::new(0.0, 0.0, 0.0),
+    );
+}
+
+// The arch at (3, -1, -3) is a portal onto the far left of the floor, looking back at the
+// cubes: it shows what the camera would see there, the cube no mirror shows included.
+#[test]
+fn through_portal() {
+    golden(
+        "through-portal",
+        Point3::new(3.0, -0.6, 1.5),
+        Point3::new(3.0, -0.7, -3.0),
+    );
+}
+
+fn golden(name: &str, eye: Point3<f32>, target: Point3<f32>) {
+    let _gpu = GPU.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+    let actual = render(eye, target).unwrap_or_else(|e| panic!("{name}: {e:#}"));
+
+    let reference_path = Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/golden")
+        .join(format!("{name}.png"));
+    if std::env::var_os("UPDATE_GOLDEN").is_some() {
+        actual.save(&reference_path).unwrap();
+        return;
+    }
+    let reference = image::open(&reference_path)
+        .unwrap_or_else(|e| {
+            panic!(
+                "{}: {e} (UPDATE_GOLDEN=1 writes it)",
+                reference_path.display()
+            )
+        })
+        .to_rgba8();
+    assert_eq!(
+        reference.dimensions(),
+        actual.dimensions(),
+        "{name}: size differs from the reference"
+    );
+
+    let (diff, differing) = compare(&reference, &actual);
+    let share = differing as f64 / (WIDTH * HEIGHT) as f64;
+    if share > MAX_DIFFERING {
+        let out = output_dir();
+        actual.save(out.join(format!("{name}-actual.png"))).unwrap();
+        diff.save(out.join(format!("{name}-diff.png"))).unwrap();
+        panic!(
+            "{name}: {differing} pixels ({:.2}%) differ from the reference, see {}",
+            share * 100.0,
+            out.display()
+        );
+    }
+}
+
+fn render(eye: Point3<f32>, target: Point3<f32>) -> anyhow::Result<image::RgbaImage> {
+    let mut desc = OffscreenDesc::new(WIDTH, HEIGHT);
+    desc.force_fallback_adapter = true;
+    let mut state = pollster::block_on(State::new_offscreen(desc))?;
+    // A single frame without `update`: no elapsed time, nothing animated
+    state.look_at(eye, target);
+    state.render()?;
+    pollster::block_on(state.read_frame())
+}
+
+// The differing pixels in red over a faded copy of the reference, and how many there are.
+fn compare(reference: &image::RgbaImage, actual: &image::RgbaImage) -> (image::RgbaImage, usize) {
+    let mut differing = 0;
+    let diff = image::RgbaImage::from_fn(reference.width(), reference.height(), |x, y| {
+        let (a, b) = (reference.get_pixel(x, y), actual.get_pixel(x, y));
+        if delta_e(a.0, b.0) > MAX_DELTA_E {
+            differing += 1;
+            image::Rgba([255, 0, 0, 255])
+        } else {
+            let gray = 64 + (a.0[0] as u32 + a.0[1] as u32 + a.0[2] as u32) / 6;
+            image::Rgba([gray as u8, gray as u8, gray as u8, 255])
+        }
+    });
+    (diff, differing)
+}
+
+// CIE76: the Euclidean distance in L*a*b* of two sRGB colors.
+fn delta_e(a: [u8; 4], b: [u8; 4]) -> f32 {
+    let (a, b) = (lab(a), lab(b));
+    ((a[0] - b[0]).powi(2) + (a[1] - b[1]).powi(2) + (a[2] - b[2]).powi(2)).sqrt()
+}
+
+fn lab([r, g, b, _]: [u8; 4]) -> [f32; 3] {
+    let linear = |c: u8| {
+        let c = c as f32 / 255.0;
+        if c <= 0.04045 {
+            c / 12.92
+        } else {
+            ((c + 0.055) / 1.055).powf(2.4)
+        }
+    };
+    let (r, g, b) = (linear(r), linear(g), linear(b));
+    // XYZ relative to the D65 white point
+    let x = (0.4124 * r + 0.3576 * g + 0.1805 * b) / 0.9505;
+    let y = 0.2126 * r + 0.7152 * g + 0.0722 * b;
+    let z = (0.0193 * r + 0.1192 * g + 0.9505 * b) / 1.089;
+    let f = |t: f32| {
+        if t > 0.008856 {
+            t.cbrt()
+        } else {
+            7.787 * t + 16.0 / 116.0
+        }
+    };
+    let (fx, fy, fz) = (f(x), f(y), f(z));
+    [116.0 * fy - 16.0, 500.0 * (fx - fy), 200.0 * (fy - fz)]
+}
+
+fn output_dir() -> PathBuf {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("target/golden");
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}